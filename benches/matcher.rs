@@ -0,0 +1,35 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use quickfuzz::matcher::{fuzzy_find, MatchOptions};
+
+/// A realistic dataset: lots of file paths sharing prefixes and extensions
+fn synthetic_paths(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!("src/module{}/file_{i}.rs", i % 50))
+        .collect()
+}
+
+fn bench_fuzzy_find(c: &mut Criterion) {
+    let options = MatchOptions::default();
+    let list = synthetic_paths(10_000);
+
+    let mut group = c.benchmark_group("fuzzy_find");
+
+    let queries = [
+        ("short", "src"),
+        ("long", "srcmodulefilers"),
+        ("no_match", "zqxjk"),
+        ("all_match", "s"),
+    ];
+
+    for (name, query) in queries {
+        group.bench_function(name, |b| {
+            b.iter(|| fuzzy_find(black_box(query), black_box(&list), &options))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_fuzzy_find);
+criterion_main!(benches);
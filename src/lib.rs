@@ -0,0 +1,7 @@
+#![forbid(unsafe_code)]
+#![forbid(unused_must_use)]
+
+//! quickfuzz's matching engine, usable independently from the interactive
+//! binary (which lives behind the default `tui` feature).
+
+pub mod matcher;
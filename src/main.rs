@@ -2,248 +2,3693 @@
 #![forbid(unused_must_use)]
 #![warn(unused_crate_dependencies)]
 
-use std::{error::Error, io, process::ExitCode};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use crossterm::{
-    event::{self, Event, KeyCode, MouseEventKind},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEventKind},
     terminal::{self, disable_raw_mode},
     ExecutableCommand,
 };
 use ratatui::{
-    prelude::{Backend, Constraint, CrosstermBackend, Direction, Layout},
-    style::{Color, Style, Stylize},
-    text::{Line, Span},
-    widgets::{List, ListItem, ListState, Paragraph},
-    Frame, Terminal,
+    layout::Corner,
+    prelude::{Backend, Constraint, CrosstermBackend, Direction, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame, Terminal, TerminalOptions, Viewport,
+};
+use quickfuzz::matcher::{
+    fuzzy_find, split_fields, Algorithm, CaseMode, FieldRange, MatchOptions, Tiebreak,
 };
-use tui_input::{backend::crossterm::EventHandler, Input};
+use regex::Regex;
+
+// Only used through the library crate
+use rayon as _;
+use unicode_normalization as _;
+use tui_input::{backend::crossterm::EventHandler, Input, InputRequest};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 fn main() -> ExitCode {
     match inner_main() {
-        Ok(()) => ExitCode::SUCCESS,
+        Ok(Outcome::Accepted) => ExitCode::SUCCESS,
+        Ok(Outcome::NoMatch) => ExitCode::from(1),
         Err(err) => {
             eprintln!("{err}");
-            ExitCode::FAILURE
+
+            // Scripts rely on distinguishing "user aborted" from real errors
+            if err.downcast_ref::<Aborted>().is_some() {
+                ExitCode::from(130)
+            } else {
+                ExitCode::FAILURE
+            }
         }
     }
 }
 
-fn inner_main() -> Result<(), Box<dyn Error>> {
-    let list = io::stdin().lines().collect::<Result<Vec<_>, _>>()?;
+/// Outcome of a run that didn't error out, mapped to the process exit code:
+/// `Accepted` exits 0, `NoMatch` exits 1 (accepting with nothing selected,
+/// or a `--filter` query matching nothing); user aborts surface as
+/// [`Aborted`] and exit 130, and real errors exit 1 with a message on stderr
+enum Outcome {
+    Accepted,
+    NoMatch,
+}
+
+/// The user aborted the selection (Esc or Ctrl-C) without accepting an entry
+#[derive(Debug)]
+struct Aborted;
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "User cancelled")
+    }
+}
+
+impl Error for Aborted {}
+
+fn inner_main() -> Result<Outcome, Box<dyn Error>> {
+    let mut options = Options::parse(std::env::args().skip(1))?;
+
+    // Entries picked before float to the top; a missing or corrupt history
+    // file is simply ignored
+    if let Some(path) = &options.history {
+        options.matching.score_bonuses = load_history_bonuses(path);
+    }
+
+    let print_index = options.print_index;
+    let print0 = options.print0;
+
+    // Reading would block forever if nothing is piped in; catch that common
+    // mistake before entering the TUI
+    if io::stdin().is_terminal() {
+        return Err("No input piped on stdin (try e.g. `ls | quickfuzz`)".into());
+    }
+
+    // Candidates stream in from a background thread so the TUI can start
+    // immediately, even while a slow producer is still running
+    let input_rx = spawn_input_reader(options.read0, options.read_records.clone());
+
+    // Non-interactive mode: print the ranked matches without ever touching
+    // the terminal (this needs the whole input, so block until EOF)
+    if let Some(query) = &options.filter {
+        let mut list = vec![];
+        let mut ansi_styles = vec![];
+        let mut seen = HashSet::new();
+        let mut input_header = vec![];
+        let mut stream_indices = StreamIndices::default();
+
+        for entry in input_rx.iter() {
+            push_entry(
+                &options,
+                entry,
+                &mut list,
+                &mut ansi_styles,
+                &mut seen,
+                &mut input_header,
+                &mut stream_indices,
+            );
+        }
+
+        if list.is_empty() {
+            return Err("No input provided on stdin".into());
+        }
+
+        if options.tac {
+            apply_tac(&mut list, &mut ansi_styles, &mut stream_indices);
+        }
+
+        let matches = fuzzy_find(query, &list, &options.matching)
+            .into_iter()
+            .map(|result| {
+                let text = list[result.original_index].clone();
+
+                (stream_indices.kept[result.original_index], text)
+            })
+            .collect::<Vec<_>>();
+
+        let outcome = if matches.is_empty() {
+            Outcome::NoMatch
+        } else {
+            Outcome::Accepted
+        };
+
+        print_entries(matches, print_index, print0);
+
+        return Ok(outcome);
+    }
+
+    // With `--select-1` / `--exit-0`, the initial query may settle the
+    // outcome before the terminal is even touched (also needs the whole
+    // input upfront)
+    let mut list = vec![];
+    let mut ansi_styles = vec![];
+    let mut seen = HashSet::new();
+    let mut input_header = vec![];
+    let mut stream_indices = StreamIndices::default();
+    let mut marked = HashSet::new();
+
+    // `--tac` needs the whole input before it can be reversed
+    if options.select_1 || options.exit_0 || options.tac {
+        for entry in input_rx.iter() {
+            push_entry(
+                &options,
+                entry,
+                &mut list,
+                &mut ansi_styles,
+                &mut seen,
+                &mut input_header,
+                &mut stream_indices,
+            );
+        }
+
+        // Deduplication already happened in stream order, so `--unique`
+        // keeps the first occurrence of the *original* input
+        if options.tac {
+            apply_tac(&mut list, &mut ansi_styles, &mut stream_indices);
+        }
+    }
+
+    // Pre-mark the entries matching `--marked` (for the pre-read paths;
+    // streamed entries are marked as they arrive)
+    if let Some(pattern) = &options.marked {
+        for (i, entry) in list.iter().enumerate() {
+            if entry.contains(pattern.as_str()) {
+                marked.insert(i);
+            }
+        }
+    }
+
+    if options.select_1 || options.exit_0 {
+        if list.is_empty() {
+            return Err("No input provided on stdin".into());
+        }
+
+        let matches = fuzzy_find(&options.query, &list, &options.matching);
+
+        if options.exit_0 && matches.is_empty() {
+            return Err("No match for the initial query".into());
+        }
+
+        if options.select_1 && matches.len() == 1 {
+            let matches = matches
+                .into_iter()
+                .map(|result| {
+                    let text = list[result.original_index].clone();
+
+                    (stream_indices.kept[result.original_index], text)
+                })
+                .collect();
+
+            print_entries(matches, print_index, print0);
+
+            return Ok(Outcome::Accepted);
+        }
+    }
+
+    // Restore the terminal before the panic message prints, otherwise a
+    // panic anywhere in the TUI leaves the user stuck in raw mode on the
+    // alternate screen, blindly typing `reset`
+    let default_panic_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        emergency_restore_terminal();
+
+        default_panic_hook(info);
+    }));
+
+    // With `--height`, the picker is rendered inline on N lines of the
+    // current screen instead of taking over with the alternate screen
+    let inline_height = match &options.height {
+        Some(height) => Some(height.resolve(terminal::size()?.1)),
+        None => None,
+    };
 
     crossterm::terminal::enable_raw_mode()?;
 
     let mut stdout = io::stdout();
 
-    stdout
-        .execute(terminal::EnterAlternateScreen)?
-        .execute(event::EnableMouseCapture)?;
+    if inline_height.is_none() {
+        stdout.execute(terminal::EnterAlternateScreen)?;
+    }
+
+    // Mouse capture breaks the terminal's own text selection, so some users
+    // prefer to opt out entirely
+    let mouse = !options.no_mouse;
+
+    if mouse {
+        stdout.execute(event::EnableMouseCapture)?;
+    }
+
+    // Let terminals deliver pastes as a single event
+    stdout.execute(event::EnableBracketedPaste)?;
 
     let backend = CrosstermBackend::new(stdout);
 
-    let mut terminal = Terminal::new(backend)?;
+    let mut terminal = match inline_height {
+        Some(height) => Terminal::with_options(
+            backend,
+            TerminalOptions {
+                viewport: Viewport::Inline(height),
+            },
+        )?,
+        None => Terminal::new(backend)?,
+    };
+
+    // `Input::new` places the cursor at the end of the prefilled query
+    let input_widget = Input::new(options.query.clone());
+
+    let print_query = options.print_query;
+    let preview_hidden = options.preview_window.hidden;
+    let has_expect = !options.expect.is_empty();
+    let history = options.history.clone();
+    let json = options.json;
+    let mut final_query = options.query.clone();
 
     let chosen = run_app(
         &mut terminal,
+        &mut final_query,
         State {
-            input_widget: Input::default(),
+            options,
+            input_widget,
             list,
+            input_rx,
+            ansi_styles,
+            seen,
+            input_header,
+            stream_indices,
+            reading_complete: false,
+            spinner_frame: 0,
             list_state: ListState::default(),
             filtered: vec![],
+            marked,
+            last_query: None,
+            preview_text: String::new(),
+            preview_for: None,
+            preview_candidate: None,
+            preview_candidate_since: Instant::now(),
+            preview_scroll: 0,
+            preview_hidden,
+            preview_area: None,
+            results_area: None,
+            status_message: None,
+            show_help: false,
+            h_scroll: 0,
+            item_heights: vec![],
+            scope_stack: vec![],
         },
-    )?;
+    );
 
+    // Restore the terminal *before* propagating any error from the app, or a
+    // cancellation would leave the terminal in raw mode
     disable_raw_mode()?;
 
+    if inline_height.is_some() {
+        // Erase the inline viewport so the picker disappears from the screen
+        terminal.clear()?;
+    } else {
+        terminal
+            .backend_mut()
+            .execute(terminal::LeaveAlternateScreen)?;
+    }
+
+    if mouse {
+        terminal.backend_mut().execute(event::DisableMouseCapture)?;
+    }
+
     terminal
         .backend_mut()
-        .execute(terminal::LeaveAlternateScreen)?
-        .execute(event::DisableMouseCapture)?;
+        .execute(event::DisableBracketedPaste)?;
 
     terminal.show_cursor()?;
 
-    print!("{chosen}");
+    // The query is reported first, and even when the user aborted (so
+    // wrappers can e.g. create an entry named after it)
+    if print_query {
+        if print0 {
+            print!("{final_query}\0");
+        } else {
+            println!("{final_query}");
+        }
+    }
+
+    let chosen = chosen?;
+
+    // Record what was picked so it ranks higher next time
+    if let Some(path) = &history {
+        append_history(path, chosen.entries.iter().map(|(_, text)| text.as_str()));
+    }
+
+    // `--json` replaces the line-based output with a single structured
+    // object, a much more robust parse target for tooling
+    if json {
+        print_json(&final_query, chosen.key.as_deref(), &chosen.entries);
 
-    Ok(())
+        return Ok(if chosen.entries.is_empty() {
+            Outcome::NoMatch
+        } else {
+            Outcome::Accepted
+        });
+    }
+
+    // With `--expect`, the accepting key comes on its own line before the
+    // selection (empty for a plain accept)
+    if has_expect {
+        let key = chosen.key.unwrap_or_default();
+
+        if print0 {
+            print!("{key}\0");
+        } else {
+            println!("{key}");
+        }
+    }
+
+    let outcome = if chosen.entries.is_empty() {
+        Outcome::NoMatch
+    } else {
+        Outcome::Accepted
+    };
+
+    print_entries(chosen.entries, print_index, print0);
+
+    Ok(outcome)
 }
 
-fn run_app<B: Backend>(
-    terminal: &mut Terminal<B>,
-    mut state: State,
-) -> Result<String, Box<dyn Error>> {
-    loop {
-        let filtered = fuzzy_find(state.input_widget.value(), &state.list);
+/// Print the outcome as a JSON object with the final query, the accepting
+/// key (when `--expect` is set), the first selection's index and value, and
+/// the full selections array (relevant with `--multi`)
+fn print_json(query: &str, key: Option<&str>, entries: &[(usize, String)]) {
+    let mut object = format!("{{\"query\":{}", json_string(query));
 
-        state.filtered = filtered
-            .into_iter()
-            .map(|result| {
-                let chars = result
-                    .chars()
-                    .map(|c| {
-                        if state.input_widget.value().contains(c) {
-                            Span::styled(c.to_string(), Style::new().underlined())
-                        } else {
-                            Span::raw(c.to_string())
-                        }
-                    })
-                    .collect::<Vec<_>>();
+    if let Some(key) = key {
+        object.push_str(&format!(",\"key\":{}", json_string(key)));
+    }
 
-                Line::from(chars)
-            })
-            .collect::<Vec<_>>();
+    if let Some((index, value)) = entries.first() {
+        object.push_str(&format!(",\"index\":{index},\"value\":{}", json_string(value)));
+    }
 
-        match state.list_state.selected() {
-            Some(selected) => {
-                if selected >= state.filtered.len() {
-                    state
-                        .list_state
-                        .select(Some(state.filtered.len().max(1) - 1));
-                }
-            }
+    let selections = entries
+        .iter()
+        .map(|(index, value)| format!("{{\"index\":{index},\"value\":{}}}", json_string(value)))
+        .collect::<Vec<_>>()
+        .join(",");
 
-            None => {
-                if !state.filtered.is_empty() {
-                    state.list_state.select(Some(0));
-                }
-            }
+    object.push_str(&format!(",\"selections\":[{selections}]}}"));
+
+    println!("{object}");
+}
+
+/// Serialize a string as a JSON string literal, escaping quotes, backslashes
+/// and control characters
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
+    }
 
-        terminal.draw(|f| draw_ui(f, &mut state))?;
+    out.push('"');
 
-        match event::read()? {
-            Event::Key(key) => match key.code {
-                KeyCode::Enter => {
-                    if let Some(selected) = state.list_state.selected() {
-                        return Ok(state.filtered[selected]
-                            .spans
-                            .iter()
-                            .map(|span| span.content.as_ref())
-                            .collect::<String>());
-                    }
-                }
+    out
+}
 
-                KeyCode::Esc => {
-                    return Err("User cancelled".into());
-                }
+/// Tracks where each kept candidate sat in the original input stream: that
+/// position is what index-based output reports, even after `--unique`
+/// dropped lines, `--tac` reversed the list, or header lines were split off
+#[derive(Default)]
+struct StreamIndices {
+    /// Stream position of each kept entry, parallel to the candidate list
+    kept: Vec<usize>,
 
-                KeyCode::Up => match state.list_state.selected() {
-                    Some(selected) => {
-                        if selected > 0 {
-                            state.list_state.select(Some(selected - 1));
-                        }
-                    }
+    /// Total entries read from the stream so far
+    read: usize,
+}
 
-                    None => {
-                        if !state.filtered.is_empty() {
-                            state.list_state.select(Some(state.filtered.len() - 1));
-                        }
-                    }
-                },
+/// Reverse the candidate list for `--tac`, keeping its parallel bookkeeping
+/// (ANSI styles and, crucially, the stream positions behind index-based
+/// output) aligned with it
+fn apply_tac(
+    list: &mut [String],
+    ansi_styles: &mut [Vec<Style>],
+    stream_indices: &mut StreamIndices,
+) {
+    list.reverse();
+    ansi_styles.reverse();
+    stream_indices.kept.reverse();
+}
 
-                KeyCode::Down => match state.list_state.selected() {
-                    Some(selected) => {
-                        if selected + 1 < state.filtered.len() {
-                            state.list_state.select(Some(selected + 1));
-                        }
-                    }
+/// Append an input entry to the candidate list, stripping and remembering its
+/// ANSI styling when `--ansi` is set (so matching operates on the visible
+/// characters only) and dropping exact duplicates when `--unique` is set
+fn push_entry(
+    options: &Options,
+    entry: String,
+    list: &mut Vec<String>,
+    ansi_styles: &mut Vec<Vec<Style>>,
+    seen: &mut HashSet<String>,
+    input_header: &mut Vec<String>,
+    stream_indices: &mut StreamIndices,
+) {
+    // Every incoming entry consumes a stream position, kept or not
+    let position = stream_indices.read;
+    stream_indices.read += 1;
+    let (mut text, mut styles) = if options.ansi {
+        let (text, styles) = parse_ansi_line(&entry);
 
-                    None => {
-                        if !state.filtered.is_empty() {
-                            state.list_state.select(Some(0));
-                        }
+        (text, Some(styles))
+    } else {
+        (entry, None)
+    };
+
+    if options.trim {
+        // Keep the per-character styles aligned with the trimmed text
+        if let Some(styles) = &mut styles {
+            let leading = text.chars().count() - text.trim_start().chars().count();
+            let trimmed_len = text.trim().chars().count();
+
+            *styles = styles[leading..leading + trimmed_len].to_vec();
+        }
+
+        text = text.trim().to_owned();
+    }
+
+    if options.skip_empty && text.is_empty() {
+        return;
+    }
+
+    // The first `--header-lines` input lines are pinned above the results
+    // instead of becoming candidates
+    if input_header.len() < options.header_lines {
+        input_header.push(text);
+        return;
+    }
+
+    // Only the first occurrence (and its position) is kept
+    if options.unique && !seen.insert(text.clone()) {
+        return;
+    }
+
+    list.push(text);
+    stream_indices.kept.push(position);
+
+    if let Some(styles) = styles {
+        ansi_styles.push(styles);
+    }
+}
+
+/// Parse a line possibly containing ANSI SGR escape sequences into its plain
+/// text and the style of each of its characters
+fn parse_ansi_line(raw: &str) -> (String, Vec<Style>) {
+    let mut text = String::new();
+    let mut styles = vec![];
+    let mut current = Style::new();
+
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+
+            let mut params = String::new();
+
+            // Parameters run until the final byte of the sequence; only SGR
+            // ('m') sequences influence the style, others are just dropped
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    if c == 'm' {
+                        current = apply_sgr_params(current, &params);
                     }
-                },
 
-                _ => {
-                    state.input_widget.handle_event(&Event::Key(key));
+                    break;
                 }
-            },
 
-            Event::Mouse(evt) => match evt.kind {
-                MouseEventKind::Down(_) => todo!(),
-                MouseEventKind::Up(_) => todo!(),
-                MouseEventKind::Drag(_) => todo!(),
-                MouseEventKind::Moved => todo!(),
-                MouseEventKind::ScrollDown => todo!(),
-                MouseEventKind::ScrollUp => todo!(),
-                MouseEventKind::ScrollLeft => todo!(),
-                MouseEventKind::ScrollRight => todo!(),
-            },
-
-            _ => {}
+                params.push(c);
+            }
+        } else {
+            text.push(c);
+            styles.push(current);
         }
     }
+
+    (text, styles)
 }
 
-fn draw_ui<B: Backend>(f: &mut Frame<B>, state: &mut State) {
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Length(1), Constraint::Length(10)])
-        .split(f.size());
+/// Apply the parameters of an SGR escape sequence to a style
+fn apply_sgr_params(mut style: Style, params: &str) -> Style {
+    let mut params = params
+        .split(';')
+        .map(|param| param.parse::<u8>().unwrap_or(0));
 
-    // === Draw input line === //
+    while let Some(param) = params.next() {
+        match param {
+            0 => style = Style::new(),
+            1 => style = style.bold(),
+            2 => style = style.dim(),
+            3 => style = style.italic(),
+            4 => style = style.underlined(),
+            7 => style = style.reversed(),
+            30..=37 => style = style.fg(ansi_basic_color(param - 30)),
+            39 => style = style.fg(Color::Reset),
+            40..=47 => style = style.bg(ansi_basic_color(param - 40)),
+            49 => style = style.bg(Color::Reset),
+            90..=97 => style = style.fg(Color::Indexed(param - 90 + 8)),
+            100..=107 => style = style.bg(Color::Indexed(param - 100 + 8)),
 
-    let scroll = state.input_widget.visual_scroll(
-        (
-            // Keep 1 space for cursor
-            chunks[0].width.max(1) - 1
-        ) as usize,
-    );
+            // Extended colors: `38;5;<n>` / `48;5;<n>` (256 colors) and
+            // `38;2;<r>;<g>;<b>` / `48;2;<r>;<g>;<b>` (truecolor)
+            38 | 48 => {
+                let color = match params.next() {
+                    Some(5) => params.next().map(Color::Indexed),
 
-    let input = Paragraph::new(state.input_widget.value()).scroll((0, scroll as u16));
+                    Some(2) => match (params.next(), params.next(), params.next()) {
+                        (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                        _ => None,
+                    },
 
-    f.render_widget(input, chunks[0]);
+                    _ => None,
+                };
 
-    f.set_cursor(
-        chunks[0].x + (state.input_widget.visual_cursor().max(scroll) - scroll) as u16,
-        chunks[0].y,
-    );
+                if let Some(color) = color {
+                    style = if param == 38 {
+                        style.fg(color)
+                    } else {
+                        style.bg(color)
+                    };
+                }
+            }
 
-    // === Draw results list === //
+            _ => {}
+        }
+    }
 
-    let results = state
-        .filtered
-        .iter()
-        .cloned()
-        .map(ListItem::new)
-        .collect::<Vec<_>>();
+    style
+}
+
+/// Map a basic ANSI color number (0-7) to its [`Color`]
+fn ansi_basic_color(number: u8) -> Color {
+    match number {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+/// A named action keys can be bound to
+#[derive(Clone, PartialEq, Eq)]
+enum Action {
+    /// Suspend the TUI and run a shell command attached to the terminal
+    Execute(String),
 
-    let results = List::new(results).highlight_style(Style::default().bg(Color::Black));
+    /// Run a shell command in the background, capturing its output
+    ExecuteSilent(String),
 
-    f.render_stateful_widget(results, chunks[1], &mut state.list_state);
+    /// Run a shell command and replace the candidate list with its output
+    Reload(String),
+
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    Accept,
+    Abort,
+    ToggleSelect,
+    SelectAll,
+    DeselectAll,
+    InvertSelection,
+    ToggleSort,
+    ToggleCase,
+    ToggleRegex,
+    ClearQuery,
+    Copy,
+    Paste,
+    Help,
+    ScrollLeft,
+    ScrollRight,
+    TogglePreview,
+    PushScope,
+    PopScope,
+    PreviewUp,
+    PreviewDown,
+    CursorStart,
+    CursorEnd,
+    CursorWordLeft,
+    CursorWordRight,
+    DeleteToStart,
+    DeleteToEnd,
+    DeleteWord,
 }
 
-fn fuzzy_find(query: &str, list: &[String]) -> Vec<String> {
-    if query.is_empty() {
-        return list.to_vec();
+impl Action {
+    /// Name used in `--bind` specs and the help overlay
+    fn name(&self) -> String {
+        match self {
+            Self::Execute(command) => return format!("execute({command})"),
+            Self::ExecuteSilent(command) => return format!("execute-silent({command})"),
+            Self::Reload(command) => return format!("reload({command})"),
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::PageUp => "page-up",
+            Self::PageDown => "page-down",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Accept => "accept",
+            Self::Abort => "abort",
+            Self::ToggleSelect => "toggle-select",
+            Self::SelectAll => "select-all",
+            Self::DeselectAll => "deselect-all",
+            Self::InvertSelection => "invert-selection",
+            Self::ToggleSort => "toggle-sort",
+            Self::ToggleCase => "toggle-case",
+            Self::ToggleRegex => "toggle-regex",
+            Self::ClearQuery => "clear-query",
+            Self::Copy => "copy",
+            Self::Paste => "paste",
+            Self::Help => "help",
+            Self::ScrollLeft => "scroll-left",
+            Self::ScrollRight => "scroll-right",
+            Self::TogglePreview => "toggle-preview",
+            Self::PushScope => "push-scope",
+            Self::PopScope => "pop-scope",
+            Self::PreviewUp => "preview-up",
+            Self::PreviewDown => "preview-down",
+            Self::CursorStart => "cursor-start",
+            Self::CursorEnd => "cursor-end",
+            Self::CursorWordLeft => "cursor-word-left",
+            Self::CursorWordRight => "cursor-word-right",
+            Self::DeleteToStart => "delete-to-start",
+            Self::DeleteToEnd => "delete-to-end",
+            Self::DeleteWord => "delete-word",
+        }
+        .to_owned()
     }
 
-    let mut scores = list
-        .iter()
-        .enumerate()
-        .map(|(i, result)| (i, compute_fuzzy_find_score(query, result)))
-        .filter(|(_, score)| *score > 0)
-        .collect::<Vec<_>>();
+    fn parse(name: &str) -> Result<Self, String> {
+        // `execute(...)` / `execute-silent(...)` carry their shell command
+        for (prefix, build) in [
+            // Order matters: `execute-silent(` must be probed before its
+            // `execute(` prefix
+            ("execute-silent(", Self::ExecuteSilent as fn(String) -> Self),
+            ("execute(", Self::Execute),
+            ("reload(", Self::Reload),
+        ] {
+            if let Some(rest) = name.strip_prefix(prefix) {
+                let command = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("Unterminated command in action: {name}"))?;
 
-    scores.sort_by_key(|(_, score)| *score);
+                return Ok(build(command.to_owned()));
+            }
+        }
 
-    scores
-        .into_iter()
-        .map(|(i, _)| list.get(i).unwrap())
-        .cloned()
-        .collect()
+        match name {
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            "page-up" => Ok(Self::PageUp),
+            "page-down" => Ok(Self::PageDown),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "accept" => Ok(Self::Accept),
+            "abort" => Ok(Self::Abort),
+            "toggle-select" => Ok(Self::ToggleSelect),
+            "select-all" => Ok(Self::SelectAll),
+            "deselect-all" => Ok(Self::DeselectAll),
+            "invert-selection" => Ok(Self::InvertSelection),
+            "toggle-sort" => Ok(Self::ToggleSort),
+            "toggle-case" => Ok(Self::ToggleCase),
+            "toggle-regex" => Ok(Self::ToggleRegex),
+            "clear-query" => Ok(Self::ClearQuery),
+            "copy" => Ok(Self::Copy),
+            "paste" => Ok(Self::Paste),
+            "help" => Ok(Self::Help),
+            "scroll-left" => Ok(Self::ScrollLeft),
+            "scroll-right" => Ok(Self::ScrollRight),
+            "toggle-preview" => Ok(Self::TogglePreview),
+            "push-scope" => Ok(Self::PushScope),
+            "pop-scope" => Ok(Self::PopScope),
+            "preview-up" => Ok(Self::PreviewUp),
+            "preview-down" => Ok(Self::PreviewDown),
+            "cursor-start" => Ok(Self::CursorStart),
+            "cursor-end" => Ok(Self::CursorEnd),
+            "cursor-word-left" => Ok(Self::CursorWordLeft),
+            "cursor-word-right" => Ok(Self::CursorWordRight),
+            "delete-to-start" => Ok(Self::DeleteToStart),
+            "delete-to-end" => Ok(Self::DeleteToEnd),
+            "delete-word" => Ok(Self::DeleteWord),
+
+            _ => Err(format!("Unknown action: {name}")),
+        }
+    }
 }
 
-fn compute_fuzzy_find_score(query: &str, subject: &str) -> usize {
-    query
-        .chars()
-        .map(|c| subject.chars().filter(|cc| c == *cc).count())
-        .sum()
+/// The built-in key bindings, applied when no `--bind` overrides the key
+fn default_action(key: &KeyEvent, query_empty: bool) -> Option<Action> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+
+    match key.code {
+        KeyCode::Enter => Some(Action::Accept),
+        KeyCode::Esc => Some(Action::Abort),
+        KeyCode::Char('c') if ctrl => Some(Action::Abort),
+        KeyCode::Char('s') if ctrl => Some(Action::ToggleSort),
+        KeyCode::Char('c') if alt => Some(Action::ToggleCase),
+        KeyCode::Char('r') if alt => Some(Action::ToggleRegex),
+
+        // Bulk multi-select operations over the current results
+        KeyCode::Char('a') if alt => Some(Action::SelectAll),
+        KeyCode::Char('d') if alt => Some(Action::DeselectAll),
+        KeyCode::Char('i') if alt => Some(Action::InvertSelection),
+
+        // Standard readline-style line editing
+        KeyCode::Char('a') if ctrl => Some(Action::CursorStart),
+        KeyCode::Char('e') if ctrl => Some(Action::CursorEnd),
+        KeyCode::Char('u') if ctrl => Some(Action::DeleteToStart),
+        KeyCode::Char('k') if ctrl => Some(Action::DeleteToEnd),
+        KeyCode::Char('w') if ctrl => Some(Action::DeleteWord),
+        KeyCode::Char('y') if ctrl => Some(Action::Copy),
+        KeyCode::Char('v') if ctrl => Some(Action::Paste),
+
+        // Interactive drill-down: lock the current results in as the new
+        // search scope, or restore the previous one
+        KeyCode::Char('p') if ctrl => Some(Action::TogglePreview),
+
+        KeyCode::Char('t') if ctrl => Some(Action::PushScope),
+        KeyCode::Char('b') if ctrl => Some(Action::PopScope),
+        // Only an empty query opens the help overlay: once the user is
+        // typing, `?` must stay a plain character (it's a regex
+        // metacharacter, and queries may legitimately contain it)
+        KeyCode::Char('?') if query_empty => Some(Action::Help),
+        KeyCode::Tab => Some(Action::ToggleSelect),
+
+        // Shift+Up / Shift+Down scroll the preview pane, independent of the
+        // results list
+        KeyCode::Up if shift => Some(Action::PreviewUp),
+        KeyCode::Down if shift => Some(Action::PreviewDown),
+
+        // Shift+Left / Shift+Right scroll long result lines horizontally
+        KeyCode::Left if shift => Some(Action::ScrollLeft),
+        KeyCode::Right if shift => Some(Action::ScrollRight),
+
+        // Word-wise cursor motion in the query
+        KeyCode::Left if alt || ctrl => Some(Action::CursorWordLeft),
+        KeyCode::Right if alt || ctrl => Some(Action::CursorWordRight),
+
+        KeyCode::Up => Some(Action::Up),
+        KeyCode::Down => Some(Action::Down),
+        KeyCode::PageUp => Some(Action::PageUp),
+        KeyCode::PageDown => Some(Action::PageDown),
+
+        // Bare Home / End move the text cursor (handled by the input
+        // widget); with Ctrl they jump through the list instead
+        KeyCode::Home if ctrl => Some(Action::First),
+        KeyCode::End if ctrl => Some(Action::Last),
+
+        _ => None,
+    }
 }
 
-struct State {
-    input_widget: Input,
-    list: Vec<String>,
-    list_state: ListState,
-    filtered: Vec<Line<'static>>,
+/// Perform an action on the state, returning `Some` with the accepted entries
+/// when the action concludes the selection
+fn perform_action(
+    action: Action,
+    state: &mut State,
+) -> Result<Option<AcceptedEntries>, Box<dyn Error>> {
+    match action {
+        // Handled directly in the event loop, which owns the terminal
+        Action::Execute(_) | Action::ExecuteSilent(_) => {}
+
+        Action::Reload(template) => {
+            let command = build_command(&template, state);
+
+            // Unlike a preview, the command's stdout *is* the new candidate
+            // list: take it whole (no line cap) and keep stderr out of it; a
+            // command that fails to even run leaves the current list alone
+            let output = match std::process::Command::new("sh").arg("-c").arg(&command).output() {
+                Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+
+                Err(err) => {
+                    state.set_status(format!("Failed to run reload command: {err}"));
+                    return Ok(None);
+                }
+            };
+
+            // The new list fully replaces the old data source: any entries
+            // still streaming in from stdin are dropped along with the old
+            // bookkeeping
+            let (_, dead_rx) = mpsc::channel();
+            state.input_rx = dead_rx;
+            state.reading_complete = true;
+
+            state.list.clear();
+            state.ansi_styles.clear();
+            state.seen.clear();
+            state.input_header.clear();
+            state.stream_indices = StreamIndices::default();
+            state.marked.clear();
+            state.scope_stack.clear();
+
+            for line in output.lines() {
+                push_entry(
+                    &state.options,
+                    line.to_owned(),
+                    &mut state.list,
+                    &mut state.ansi_styles,
+                    &mut state.seen,
+                    &mut state.input_header,
+                    &mut state.stream_indices,
+                );
+            }
+
+            state.list_state.select(None);
+            state.last_query = None;
+        }
+
+        Action::Up => state.select_previous(),
+        Action::Down => state.select_next(),
+        Action::PageUp => state.move_selection_up(state.page_size()),
+        Action::PageDown => state.move_selection_down(state.page_size()),
+        Action::First => state.select_first(),
+        Action::Last => state.select_last(),
+
+        Action::Accept => {
+            // In multi-select mode, accept every marked entry; fall back to
+            // the highlighted one when nothing is marked
+            if state.options.multi && !state.marked.is_empty() {
+                let mut marked = state.marked.iter().copied().collect::<Vec<_>>();
+                marked.sort_unstable();
+
+                return Ok(Some(
+                    marked
+                        .into_iter()
+                        .map(|i| (state.stream_index(i), state.list[i].clone()))
+                        .collect(),
+                ));
+            }
+
+            if let Some(entry) = state.selected_entry() {
+                return Ok(Some(vec![entry]));
+            }
+
+            // Under `--print-query`, accepting with nothing selected still
+            // concludes (exit code 1), so wrappers can act on the query
+            if state.options.print_query {
+                return Ok(Some(vec![]));
+            }
+        }
+
+        Action::Abort => return Err(Aborted.into()),
+
+        Action::ToggleSelect => {
+            if state.options.multi {
+                if let Some(original_index) = state
+                    .list_state
+                    .selected()
+                    .and_then(|selected| state.filtered.get(selected))
+                    .map(|entry| entry.original_index)
+                {
+                    if !state.marked.remove(&original_index) {
+                        state.marked.insert(original_index);
+                    }
+
+                    state.select_next();
+                }
+            }
+        }
+
+        // The bulk mark operations act on the *currently filtered* results,
+        // not on entries hidden by the query
+        Action::SelectAll if state.options.multi => {
+            for i in 0..state.filtered.len() {
+                state.marked.insert(state.filtered[i].original_index);
+            }
+        }
+
+        Action::DeselectAll if state.options.multi => {
+            for i in 0..state.filtered.len() {
+                state.marked.remove(&state.filtered[i].original_index);
+            }
+        }
+
+        Action::InvertSelection if state.options.multi => {
+            for i in 0..state.filtered.len() {
+                let original_index = state.filtered[i].original_index;
+
+                if !state.marked.remove(&original_index) {
+                    state.marked.insert(original_index);
+                }
+            }
+        }
+
+        Action::SelectAll | Action::DeselectAll | Action::InvertSelection => {}
+
+        Action::ToggleSort => {
+            state.options.matching.no_sort = !state.options.matching.no_sort;
+
+            // The results changed even though the query didn't
+            state.last_query = None;
+        }
+
+        Action::ToggleCase => {
+            state.options.matching.case = state.options.matching.case.next();
+            state.set_status(format!("Case: {}", state.options.matching.case.name()));
+
+            // The results (and their highlights) changed even though the
+            // query didn't
+            state.last_query = None;
+        }
+
+        Action::ToggleRegex => {
+            state.options.matching.regex = !state.options.matching.regex;
+
+            state.set_status(if state.options.matching.regex {
+                "Regex mode"
+            } else {
+                "Fuzzy mode"
+            });
+
+            state.last_query = None;
+        }
+
+        Action::ClearQuery => state.input_widget.reset(),
+
+        Action::Paste => match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => insert_into_query(state, &text),
+            Err(err) => state.set_status(format!("Clipboard error: {err}")),
+        },
+
+        Action::Help => state.show_help = true,
+
+        Action::ScrollLeft => state.h_scroll = state.h_scroll.saturating_sub(H_SCROLL_STEP),
+        Action::ScrollRight => state.h_scroll = state.h_scroll.saturating_add(H_SCROLL_STEP),
+
+        Action::TogglePreview => state.preview_hidden = !state.preview_hidden,
+
+        Action::PushScope => {
+            if !state.filtered.is_empty() {
+                let mut indices = state
+                    .filtered
+                    .iter()
+                    .map(|entry| entry.original_index)
+                    .collect::<Vec<_>>();
+                indices.sort_unstable();
+
+                let list = indices
+                    .iter()
+                    .map(|i| state.list[*i].clone())
+                    .collect::<Vec<_>>();
+
+                let ansi_styles = if state.ansi_styles.is_empty() {
+                    vec![]
+                } else {
+                    indices
+                        .iter()
+                        .map(|i| state.ansi_styles[*i].clone())
+                        .collect()
+                };
+
+                let kept = indices
+                    .iter()
+                    .map(|i| state.stream_index(*i))
+                    .collect::<Vec<_>>();
+
+                // Note: entries still streaming in will join the new scope,
+                // and marks don't survive the renumbering
+                state.scope_stack.push(Scope {
+                    list: std::mem::replace(&mut state.list, list),
+                    ansi_styles: std::mem::replace(&mut state.ansi_styles, ansi_styles),
+                    kept: std::mem::replace(&mut state.stream_indices.kept, kept),
+                });
+
+                state.marked.clear();
+                state.input_widget.reset();
+                state.last_query = None;
+            }
+        }
+
+        Action::PopScope => {
+            if let Some(scope) = state.scope_stack.pop() {
+                state.list = scope.list;
+                state.ansi_styles = scope.ansi_styles;
+                state.stream_indices.kept = scope.kept;
+                state.marked.clear();
+                state.last_query = None;
+            }
+        }
+
+        Action::Copy => {
+            // Copy all marked entries in multi-select mode, otherwise the
+            // highlighted one; clipboard failures (e.g. headless session)
+            // only surface as a status message
+            let text = if state.options.multi && !state.marked.is_empty() {
+                let mut marked = state.marked.iter().copied().collect::<Vec<_>>();
+                marked.sort_unstable();
+
+                Some(
+                    marked
+                        .into_iter()
+                        .map(|i| state.list[i].clone())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            } else {
+                state.selected_entry().map(|(_, text)| text)
+            };
+
+            if let Some(text) = text {
+                match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text))
+                {
+                    Ok(()) => state.set_status("Copied to clipboard"),
+                    Err(err) => state.set_status(format!("Clipboard error: {err}")),
+                }
+            }
+        }
+
+        Action::PreviewUp => state.scroll_preview_up(1),
+        Action::PreviewDown => state.scroll_preview_down(1),
+
+        Action::CursorStart => {
+            state.input_widget.handle(InputRequest::GoToStart);
+        }
+
+        Action::CursorEnd => {
+            state.input_widget.handle(InputRequest::GoToEnd);
+        }
+
+        // The input widget clamps at the ends and skips separator runs
+        Action::CursorWordLeft => {
+            state.input_widget.handle(InputRequest::GoToPrevWord);
+        }
+
+        Action::CursorWordRight => {
+            state.input_widget.handle(InputRequest::GoToNextWord);
+        }
+
+        Action::DeleteToStart => {
+            // The input widget has no "delete to start" request: rebuild it
+            // from whatever follows the cursor
+            let rest = state
+                .input_widget
+                .value()
+                .chars()
+                .skip(state.input_widget.cursor())
+                .collect::<String>();
+
+            state.input_widget = Input::new(rest).with_cursor(0);
+        }
+
+        Action::DeleteToEnd => {
+            state.input_widget.handle(InputRequest::DeleteTillEnd);
+        }
+
+        Action::DeleteWord => {
+            state.input_widget.handle(InputRequest::DeletePrevWord);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether a key event is plain text editing, fit for the input widget:
+/// printable characters without a control modifier, deletions, and cursor
+/// movement
+fn is_text_editing_key(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char(_) => {
+            !key.modifiers.contains(KeyModifiers::CONTROL)
+                && !key.modifiers.contains(KeyModifiers::ALT)
+        }
+
+        KeyCode::Backspace
+        | KeyCode::Delete
+        | KeyCode::Left
+        | KeyCode::Right
+        | KeyCode::Home
+        | KeyCode::End => true,
+
+        _ => false,
+    }
+}
+
+/// Insert pasted text into the query at the cursor, flattening multi-line
+/// content onto a single line
+fn insert_into_query(state: &mut State, text: &str) {
+    for c in text.chars() {
+        let c = match c {
+            '\r' => continue,
+            '\n' => ' ',
+            c => c,
+        };
+
+        state.input_widget.handle(InputRequest::InsertChar(c));
+    }
+}
+
+/// Split a `--bind` value on commas, except inside parentheses so
+/// `ctrl-o:execute(cmd a,b),ctrl-k:up` parses as two bindings
+fn split_bind_specs(value: &str) -> Vec<&str> {
+    let mut specs = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+
+            ',' if depth == 0 => {
+                specs.push(&value[start..i]);
+                start = i + 1;
+            }
+
+            _ => {}
+        }
+    }
+
+    specs.push(&value[start..]);
+
+    specs
+}
+
+/// Parse a key spec such as `ctrl-j`, `alt-enter` or `page-up` into the
+/// `(code, modifiers)` pair reported by crossterm
+fn parse_key_combo(spec: &str) -> Result<(KeyCode, KeyModifiers), String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = spec;
+
+    loop {
+        let modifier = match key.split_once('-') {
+            Some(("ctrl", rest)) => {
+                key = rest;
+                KeyModifiers::CONTROL
+            }
+            Some(("alt", rest)) => {
+                key = rest;
+                KeyModifiers::ALT
+            }
+            Some(("shift", rest)) => {
+                key = rest;
+                KeyModifiers::SHIFT
+            }
+            _ => break,
+        };
+
+        modifiers |= modifier;
+    }
+
+    let code = match key {
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "page-up" => KeyCode::PageUp,
+        "page-down" => KeyCode::PageDown,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+
+        key => {
+            let mut chars = key.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(format!("Unknown key in binding: {spec}")),
+            }
+        }
+    };
+
+    Ok((code, modifiers))
+}
+
+/// Maximum number of preview output lines kept in memory
+const PREVIEW_MAX_LINES: usize = 500;
+
+/// How long the selection must sit still before the preview command runs
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// How long transient status messages stay visible
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+/// Maximum streamed entries ingested per event-loop iteration, so a very
+/// fast producer can't starve redraws
+const MAX_ENTRIES_PER_TICK: usize = 50_000;
+
+/// Display columns moved per horizontal scroll step
+const H_SCROLL_STEP: usize = 4;
+
+/// Drop the first `columns` display columns of a line, marking the cut edge
+/// with an ellipsis (wide characters straddling the cut are dropped whole)
+fn scroll_line_left(line: Line<'static>, columns: usize) -> Line<'static> {
+    if columns == 0 {
+        return line;
+    }
+
+    let mut skipped = 0;
+    let mut spans = vec![Span::raw("…")];
+
+    for span in line.spans {
+        if skipped >= columns {
+            spans.push(span);
+            continue;
+        }
+
+        let span_width = span.width();
+
+        if skipped + span_width <= columns {
+            skipped += span_width;
+            continue;
+        }
+
+        // The cut lands inside this span
+        let mut content = String::new();
+
+        for c in span.content.chars() {
+            if skipped < columns {
+                skipped += UnicodeWidthChar::width(c).unwrap_or(0);
+            } else {
+                content.push(c);
+            }
+        }
+
+        spans.push(Span::styled(content, span.style));
+    }
+
+    Line::from(spans)
+}
+
+/// Wrap a line onto as many rows as needed to fit `width` display columns,
+/// preserving span styles across the cuts
+fn wrap_line(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line];
+    }
+
+    let mut lines = vec![];
+    let mut current: Vec<Span> = vec![];
+    let mut used = 0;
+
+    for span in line.spans {
+        let mut content = String::new();
+
+        for c in span.content.chars() {
+            let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+
+            if used + char_width > width {
+                if !content.is_empty() {
+                    current.push(Span::styled(std::mem::take(&mut content), span.style));
+                }
+
+                lines.push(Line::from(std::mem::take(&mut current)));
+                used = 0;
+            }
+
+            content.push(c);
+            used += char_width;
+        }
+
+        if !content.is_empty() {
+            current.push(Span::styled(content, span.style));
+        }
+    }
+
+    if !current.is_empty() {
+        lines.push(Line::from(current));
+    }
+
+    lines
+}
+
+/// Rebuild a per-character styled line with its fields padded to the given
+/// column widths (rows with fewer fields are simply shorter)
+fn align_line_columns(
+    line: Line<'static>,
+    delimiter: Option<&str>,
+    widths: &[usize],
+) -> Line<'static> {
+    let text = line
+        .spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect::<String>();
+
+    let fields = split_fields(&text, delimiter);
+    let mut spans = vec![];
+
+    for (i, (start, field)) in fields.iter().enumerate() {
+        let chars = field.chars().count();
+
+        // The line's spans are one per character, so fields can be sliced
+        // out by their character positions
+        spans.extend(line.spans[*start..*start + chars].iter().cloned());
+
+        if i + 1 < fields.len() {
+            let width = widths.get(i).copied().unwrap_or(0);
+            let padding = width.saturating_sub(field.as_str().width()) + 1;
+
+            spans.push(Span::raw(" ".repeat(padding)));
+        }
+    }
+
+    Line::from(spans)
+}
+
+/// Split a per-character styled line into one row per newline character
+/// (the newlines themselves are dropped)
+fn split_line_at_newlines(line: Line<'static>) -> Vec<Line<'static>> {
+    let mut rows = vec![];
+    let mut current = vec![];
+
+    for span in line.spans {
+        if span.content.as_ref() == "\n" {
+            rows.push(Line::from(std::mem::take(&mut current)));
+        } else {
+            current.push(span);
+        }
+    }
+
+    rows.push(Line::from(current));
+
+    rows
+}
+
+/// Truncate a line to `max_columns` display columns, marking the cut edge
+/// with an ellipsis
+fn trim_line_to_width(line: Line<'static>, max_columns: usize) -> Line<'static> {
+    if line.width() <= max_columns || max_columns == 0 {
+        return line;
+    }
+
+    let budget = max_columns - 1;
+    let mut used = 0;
+    let mut spans = vec![];
+
+    for span in line.spans {
+        if used + span.width() <= budget {
+            used += span.width();
+            spans.push(span);
+            continue;
+        }
+
+        let mut content = String::new();
+
+        for c in span.content.chars() {
+            let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+
+            if used + char_width > budget {
+                break;
+            }
+
+            used += char_width;
+            content.push(c);
+        }
+
+        spans.push(Span::styled(content, span.style));
+        break;
+    }
+
+    spans.push(Span::raw("…"));
+
+    Line::from(spans)
+}
+
+/// Animation frames of the spinner shown while input is still streaming in
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Substitute the supported placeholders into a command template, each value
+/// shell-quoted so spaces and quotes can't break the command apart: `{}` is
+/// the current selection, `{n}` its input-stream index, `{q}` the current
+/// query and `{+}` all marked selections
+fn build_command(template: &str, state: &State) -> String {
+    let selected = state.selected_entry();
+
+    let entry = selected
+        .as_ref()
+        .map(|(_, text)| text.as_str())
+        .unwrap_or_default();
+
+    let index = selected.as_ref().map(|(index, _)| *index).unwrap_or(0);
+
+    // All marked selections, falling back to the current one when nothing is
+    // marked
+    let marked = if state.marked.is_empty() {
+        shell_quote(entry)
+    } else {
+        let mut marked = state.marked.iter().copied().collect::<Vec<_>>();
+        marked.sort_unstable();
+
+        marked
+            .into_iter()
+            .map(|i| shell_quote(&state.list[i]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    // Substitute in a single left-to-right scan, so placeholder-looking
+    // sequences *inside* a substituted value (an entry literally named
+    // `a{q}b`, say) are never expanded themselves
+    let mut command = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(brace) = rest.find('{') {
+        command.push_str(&rest[..brace]);
+        rest = &rest[brace..];
+
+        let substitution = [
+            ("{}", shell_quote(entry)),
+            ("{n}", index.to_string()),
+            ("{q}", shell_quote(state.input_widget.value())),
+            ("{+}", marked.clone()),
+        ]
+        .into_iter()
+        .find(|(placeholder, _)| rest.starts_with(placeholder));
+
+        match substitution {
+            Some((placeholder, value)) => {
+                command.push_str(&value);
+                rest = &rest[placeholder.len()..];
+            }
+
+            None => {
+                command.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    command.push_str(rest);
+
+    command
+}
+
+/// Run a shell command and capture its output
+fn run_shell_command(command: &str) -> String {
+    match std::process::Command::new("sh").arg("-c").arg(command).output() {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .take(PREVIEW_MAX_LINES)
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            // Surface failures in the pane instead of silently showing
+            // nothing
+            if !output.status.success() {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+
+                text.push_str(String::from_utf8_lossy(&output.stderr).trim_end());
+            }
+
+            text
+        }
+
+        Err(err) => format!("Failed to run preview command: {err}"),
+    }
+}
+
+/// Quote a value so it is passed to `sh -c` as a single word
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Load the history file into per-entry frecency score bonuses: each past
+/// pick contributes a bonus that decays with its age. Unreadable files and
+/// malformed lines are silently ignored.
+fn load_history_bonuses(path: &Path) -> HashMap<String, usize> {
+    let mut bonuses = HashMap::new();
+
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return bonuses;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    for line in content.lines() {
+        let Some((timestamp, entry)) = line.split_once(' ') else {
+            continue;
+        };
+
+        let Ok(timestamp) = timestamp.parse::<u64>() else {
+            continue;
+        };
+
+        let age = now.saturating_sub(timestamp);
+
+        let bonus = if age < 3600 {
+            512
+        } else if age < 86_400 {
+            256
+        } else if age < 7 * 86_400 {
+            128
+        } else {
+            64
+        };
+
+        *bonuses.entry(entry.to_owned()).or_insert(0) += bonus;
+    }
+
+    bonuses
+}
+
+/// Append the accepted entries to the history file, with the current
+/// timestamp (failures are not worth aborting over)
+fn append_history<'a>(path: &Path, entries: impl Iterator<Item = &'a str>) {
+    let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    else {
+        return;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    for entry in entries {
+        let _ = writeln!(file, "{now} {entry}");
+    }
+}
+
+/// Best-effort terminal restoration for the panic hook: every mode the TUI
+/// may have enabled is torn down, ignoring errors (some modes may not have
+/// been entered yet)
+fn emergency_restore_terminal() {
+    let _ = disable_raw_mode();
+
+    let mut stdout = io::stdout();
+
+    let _ = stdout.execute(terminal::LeaveAlternateScreen);
+    let _ = stdout.execute(event::DisableMouseCapture);
+    let _ = stdout.execute(event::DisableBracketedPaste);
+    let _ = stdout.execute(crossterm::cursor::Show);
+}
+
+/// Read stdin on a background thread, sending entries over a channel as they
+/// arrive (the channel disconnects once the input is exhausted)
+fn spawn_input_reader(read0: bool, records_separator: Option<String>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        // Records mode: split the whole input on the separator string, so a
+        // single entry can span several lines (unlike `--read0`, which is
+        // about the byte separator of single-line entries)
+        if let Some(separator) = records_separator {
+            let mut bytes = vec![];
+
+            if io::stdin().read_to_end(&mut bytes).is_err() {
+                return;
+            }
+
+            let content = String::from_utf8_lossy(&bytes);
+
+            for record in content.split(&separator) {
+                let record = record.trim_matches('\n');
+
+                if record.is_empty() {
+                    continue;
+                }
+
+                if tx.send(record.to_owned()).is_err() {
+                    return;
+                }
+            }
+        } else if read0 {
+            // Split on NUL bytes instead of newlines, for entries that may
+            // themselves contain newlines (à la `find -print0`)
+            let mut bytes = vec![];
+
+            if io::stdin().read_to_end(&mut bytes).is_err() {
+                return;
+            }
+
+            for chunk in bytes.split(|byte| *byte == 0).filter(|c| !c.is_empty()) {
+                // Entries (e.g. filenames) can contain arbitrary bytes; a
+                // lossy decode keeps them selectable instead of killing the
+                // whole session
+                let entry = String::from_utf8_lossy(chunk).into_owned();
+
+                if tx.send(entry).is_err() {
+                    return;
+                }
+            }
+        } else {
+            let mut stdin = io::stdin().lock();
+            let mut buf = vec![];
+
+            loop {
+                buf.clear();
+
+                match stdin.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => return,
+
+                    Ok(_) => {
+                        if buf.last() == Some(&b'\n') {
+                            buf.pop();
+
+                            if buf.last() == Some(&b'\r') {
+                                buf.pop();
+                            }
+                        }
+
+                        // Same as above: decode each line lossily so one bad
+                        // byte doesn't abort everything
+                        let line = String::from_utf8_lossy(&buf).into_owned();
+
+                        if tx.send(line).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+/// Print the accepted entries to stdout, honoring `--print-index` and
+/// `--print0`
+fn print_entries(entries: Vec<(usize, String)>, print_index: bool, print0: bool) {
+    let entries = entries
+        .into_iter()
+        .map(|(index, text)| {
+            if print_index {
+                index.to_string()
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if print0 {
+        // Each entry is NUL-terminated, for consumption by `xargs -0` & co.
+        for entry in entries {
+            print!("{entry}\0");
+        }
+    } else {
+        print!("{}", entries.join("\n"));
+    }
+}
+
+/// Entries accepted by the user, as `(original index, text)` pairs
+type AcceptedEntries = Vec<(usize, String)>;
+
+/// A successful selection, along with the `--expect` key that triggered it
+/// (if any)
+struct Acceptance {
+    key: Option<String>,
+    entries: AcceptedEntries,
+}
+
+fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    final_query: &mut String,
+    mut state: State,
+) -> Result<Acceptance, Box<dyn Error>> {
+    loop {
+        // Pull in the entries that streamed in since the last iteration. The
+        // list is only ever mutated here, on the UI thread, so the counter
+        // and filter can never observe it mid-update; capping the batch
+        // keeps redraws (and the climbing total) flowing even when the
+        // producer outpaces us.
+        let mut received_new_entries = false;
+        let mut drained = 0;
+
+        while drained < MAX_ENTRIES_PER_TICK {
+            drained += 1;
+
+            match state.input_rx.try_recv() {
+                Ok(entry) => {
+                    let before = state.list.len();
+
+                    push_entry(
+                        &state.options,
+                        entry,
+                        &mut state.list,
+                        &mut state.ansi_styles,
+                        &mut state.seen,
+                        &mut state.input_header,
+                        &mut state.stream_indices,
+                    );
+
+                    // Streamed entries matching `--marked` start out marked
+                    if state.list.len() > before {
+                        if let Some(pattern) = &state.options.marked {
+                            if state.list[before].contains(pattern.as_str()) {
+                                state.marked.insert(before);
+                            }
+                        }
+                    }
+
+                    received_new_entries = true;
+                }
+
+                Err(mpsc::TryRecvError::Empty) => break,
+
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    state.reading_complete = true;
+                    break;
+                }
+            }
+        }
+
+        if received_new_entries {
+            // The candidate set changed even though the query didn't
+            state.last_query = None;
+        }
+
+        // Filtering is only recomputed when the query actually changed;
+        // pure navigation events reuse the cached results
+        if state.last_query.as_deref() != Some(state.input_widget.value()) {
+            state.refilter();
+
+            state.last_query = Some(state.input_widget.value().to_owned());
+        }
+
+        // Refresh the preview when the highlighted entry changed, but only
+        // once the selection has been stable for a short moment: the command
+        // may be slow, and holding Down must not degrade the TUI to one
+        // frame per invocation
+        if state.options.preview.is_some() {
+            let selected = state.selected_entry();
+            let selected_index = selected.as_ref().map(|(i, _)| *i);
+
+            if state.preview_candidate != selected_index {
+                state.preview_candidate = selected_index;
+                state.preview_candidate_since = Instant::now();
+            }
+
+            if state.preview_for != selected_index
+                && (state.preview_candidate_since.elapsed() >= PREVIEW_DEBOUNCE
+                    || selected_index.is_none())
+            {
+                state.preview_text = match &selected {
+                    Some(_) => {
+                        let template = state.options.preview.as_deref().unwrap();
+
+                        run_shell_command(&build_command(template, &state))
+                    }
+
+                    None => String::new(),
+                };
+
+                state.preview_for = selected_index;
+
+                // New content starts back at the top
+                state.preview_scroll = 0;
+            }
+        }
+
+        terminal.draw(|f| draw_ui(f, &mut state))?;
+
+        // Wake up on a tick even without user input, so freshly streamed
+        // entries show up on their own; while idle the cost is a single
+        // `poll` per tick
+        if !event::poll(state.options.tick_rate)? {
+            continue;
+        }
+
+        match event::read()? {
+            Event::Key(key) => {
+                // While the help overlay is up, keys only dismiss it (it is
+                // purely visual: query and selection are left untouched)
+                if state.show_help {
+                    if matches!(key.code, KeyCode::Char('?') | KeyCode::Esc) {
+                        state.show_help = false;
+                    }
+
+                    continue;
+                }
+
+                // Custom `--bind`s take precedence over the default bindings;
+                // keys bound to nothing fall through to the input widget
+                let action = state
+                    .options
+                    .bindings
+                    .get(&(key.code, key.modifiers))
+                    .cloned()
+                    .or_else(|| default_action(&key, state.input_widget.value().is_empty()));
+
+                // `--expect` keys accept the selection and are reported back
+                let expect_key = state
+                    .options
+                    .expect
+                    .iter()
+                    .find(|(combo, _)| *combo == (key.code, key.modifiers))
+                    .map(|(_, spec)| spec.clone());
+
+                if let Some(spec) = expect_key {
+                    *final_query = state.input_widget.value().to_owned();
+
+                    if let Some(entries) = perform_action(Action::Accept, &mut state)? {
+                        return Ok(Acceptance {
+                            key: Some(spec),
+                            entries,
+                        });
+                    }
+
+                    continue;
+                }
+
+                match action {
+                    // Foreground execution needs the terminal: suspend the
+                    // TUI, run the command attached to it, then resume with
+                    // the list and query intact
+                    Some(Action::Execute(template)) => {
+                        let command = build_command(&template, &state);
+
+                        // Hand the terminal over to the child command,
+                        // mirroring the main setup/teardown: with `--height`
+                        // the alternate screen was never entered, and the
+                        // child must not receive mouse/paste escape
+                        // sequences meant for us
+                        let inline = state.options.height.is_some();
+                        let mouse = !state.options.no_mouse;
+
+                        disable_raw_mode()?;
+
+                        if mouse {
+                            io::stdout().execute(event::DisableMouseCapture)?;
+                        }
+
+                        io::stdout().execute(event::DisableBracketedPaste)?;
+
+                        if !inline {
+                            io::stdout().execute(terminal::LeaveAlternateScreen)?;
+                        }
+
+                        terminal.show_cursor()?;
+
+                        let _ = std::process::Command::new("sh")
+                            .arg("-c")
+                            .arg(&command)
+                            .status();
+
+                        crossterm::terminal::enable_raw_mode()?;
+
+                        if !inline {
+                            io::stdout().execute(terminal::EnterAlternateScreen)?;
+                        }
+
+                        io::stdout().execute(event::EnableBracketedPaste)?;
+
+                        if mouse {
+                            io::stdout().execute(event::EnableMouseCapture)?;
+                        }
+
+                        // Force a full repaint of the restored screen
+                        terminal.clear()?;
+                    }
+
+                    Some(Action::ExecuteSilent(template)) => {
+                        let command = build_command(&template, &state);
+                        let output = run_shell_command(&command);
+
+                        // Surface a hint of what happened without leaving
+                        // the TUI
+                        match output.lines().next() {
+                            Some(first) => state.set_status(first.to_owned()),
+                            None => state.set_status("Command finished"),
+                        }
+                    }
+
+                    Some(action) => {
+                        // Capture the query for `--print-query` before any
+                        // accept/abort path leaves the loop
+                        *final_query = state.input_widget.value().to_owned();
+
+                        if let Some(entries) = perform_action(action, &mut state)? {
+                            return Ok(Acceptance { key: None, entries });
+                        }
+                    }
+
+                    None => {
+                        // Only clean text-editing keys reach the input
+                        // widget, so unbound control combinations can't end
+                        // up inserted as literal characters in the query
+                        // (Backspace on an empty query is already a no-op
+                        // inside the widget)
+                        if is_text_editing_key(&key) {
+                            state.input_widget.handle_event(&Event::Key(key));
+                        }
+                    }
+                }
+            }
+
+            // No mouse events should arrive with `--no-mouse` (capture is
+            // off), but stay consistent if the terminal sends them anyway
+            Event::Mouse(_) if state.options.no_mouse => {}
+
+            Event::Mouse(evt) => match evt.kind {
+                MouseEventKind::ScrollUp => {
+                    if state.preview_contains(evt.column, evt.row) {
+                        state.scroll_preview_up(1);
+                    } else {
+                        state.select_previous();
+                    }
+                }
+
+                MouseEventKind::ScrollDown => {
+                    if state.preview_contains(evt.column, evt.row) {
+                        state.scroll_preview_down(1);
+                    } else {
+                        state.select_next();
+                    }
+                }
+
+                MouseEventKind::Down(_) => {
+                    if let Some(index) = state.hit_test_result(evt.column, evt.row) {
+                        // Clicking the already-selected row (e.g. the second
+                        // half of a double-click) accepts it like Enter does
+                        if state.list_state.selected() == Some(index) {
+                            *final_query = state.input_widget.value().to_owned();
+
+                            // Same accept path as Enter, so multi-select and
+                            // any other accept semantics stay consistent
+                            if let Some(entries) = perform_action(Action::Accept, &mut state)? {
+                                return Ok(Acceptance { key: None, entries });
+                            }
+                        } else {
+                            state.list_state.select(Some(index));
+                        }
+                    }
+                }
+
+                // Other mouse events are swallowed without acting since mouse
+                // capture is enabled
+                _ => {}
+            },
+
+            // Bracketed paste delivers the whole pasted text as one chunk
+            // instead of a storm of key events
+            Event::Paste(text) => insert_into_query(&mut state, &text),
+
+            // Looping back is enough: the next `terminal.draw` recomputes the
+            // whole layout (and thus page size and visible rows) from the new
+            // terminal dimensions
+            Event::Resize(_, _) => {}
+
+            _ => {}
+        }
+    }
+}
+
+fn draw_ui<B: Backend>(f: &mut Frame<B>, state: &mut State) {
+    // With a preview command, part of the screen is handed over to the
+    // preview pane, as configured by `--preview-window`
+    let (main_area, preview_area) = if state.options.preview.is_some() && !state.preview_hidden {
+        let window = &state.options.preview_window;
+
+        let (direction, border) = match window.position {
+            PreviewPosition::Right => (Direction::Horizontal, Borders::LEFT),
+            PreviewPosition::Down => (Direction::Vertical, Borders::TOP),
+        };
+
+        let size = match window.size {
+            Height::Lines(lines) => Constraint::Length(lines),
+            Height::Percent(percent) => Constraint::Percentage(percent),
+        };
+
+        let halves = Layout::default()
+            .direction(direction)
+            .constraints([Constraint::Min(1), size])
+            .split(f.size());
+
+        (halves[0], Some((halves[1], border)))
+    } else {
+        (f.size(), None)
+    };
+
+    state.preview_area = preview_area.map(|(area, _)| area);
+
+    if let Some((preview_area, border)) = preview_area {
+        let preview = Paragraph::new(state.preview_text.as_str())
+            .block(Block::default().borders(border))
+            .scroll((state.preview_scroll, 0));
+
+        f.render_widget(preview, preview_area);
+    }
+
+    // The header is pinned between the counter and the results, and may span
+    // several lines: first the ones captured by `--header-lines`, then the
+    // `--header` text
+    let mut header_lines = state
+        .input_header
+        .iter()
+        .map(|line| line.as_str())
+        .collect::<Vec<_>>();
+
+    if let Some(header) = &state.options.header {
+        header_lines.extend(header.lines());
+    }
+
+    let header_height = header_lines.len() as u16;
+
+    // In reverse layout the prompt sits below the results and the list is
+    // anchored to the bottom, so the best matches stay next to the prompt
+    let constraints = if state.options.layout.prompt_at_bottom() {
+        [
+            Constraint::Min(1),
+            Constraint::Length(header_height),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ]
+    } else {
+        [
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(header_height),
+            Constraint::Min(1),
+        ]
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(main_area);
+
+    let (input_area, counter_area, header_area, results_area) = if state.options.layout.prompt_at_bottom() {
+        (chunks[3], chunks[2], chunks[1], chunks[0])
+    } else {
+        (chunks[0], chunks[1], chunks[2], chunks[3])
+    };
+
+    // === Draw header === //
+
+    if !header_lines.is_empty() {
+        let header_style = if state.options.color {
+            Style::new().yellow()
+        } else {
+            Style::new()
+        };
+
+        let header = Paragraph::new(header_lines.join("\n")).style(header_style);
+
+        f.render_widget(header, header_area);
+    }
+
+    // === Draw prompt and input line === //
+
+    // Reserve the prompt's columns so the input scroll and cursor math are
+    // unaffected by its width; measured in display cells, since CJK
+    // characters and emojis are wider than one column
+    let prompt_width = state.options.prompt.as_str().width() as u16;
+
+    let input_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(prompt_width), Constraint::Min(1)])
+        .split(input_area);
+
+    let prompt_style = if state.options.color {
+        Style::new().fg(state.options.theme.prompt)
+    } else {
+        Style::new()
+    };
+
+    let prompt = Paragraph::new(Span::styled(state.options.prompt.clone(), prompt_style));
+
+    f.render_widget(prompt, input_chunks[0]);
+
+    let scroll = state.input_widget.visual_scroll(
+        (
+            // Keep 1 space for cursor
+            input_chunks[1].width.max(1) - 1
+        ) as usize,
+    );
+
+    let input = Paragraph::new(state.input_widget.value()).scroll((0, scroll as u16));
+
+    f.render_widget(input, input_chunks[1]);
+
+    f.set_cursor(
+        input_chunks[1].x + (state.input_widget.visual_cursor().max(scroll) - scroll) as u16,
+        input_chunks[1].y,
+    );
+
+    // === Draw match counter === //
+
+    // A small spinner reassures the user that more entries may still stream
+    // in; it advances with the redraw tick
+    let spinner = if state.reading_complete {
+        ' '
+    } else {
+        state.spinner_frame = (state.spinner_frame + 1) % SPINNER_FRAMES.len();
+
+        SPINNER_FRAMES[state.spinner_frame]
+    };
+
+    let mut counter = format!("{spinner} {}/{}", state.filtered.len(), state.list.len());
+
+    if state.options.multi {
+        counter.push_str(&format!(" ({} marked)", state.marked.len()));
+    }
+
+    // Transient status messages (e.g. clipboard feedback) expire on their own
+    if state
+        .status_message
+        .as_ref()
+        .is_some_and(|(_, since)| since.elapsed() > STATUS_MESSAGE_DURATION)
+    {
+        state.status_message = None;
+    }
+
+    if state.options.matching.case != CaseMode::Smart {
+        counter.push_str(&format!(" [case: {}]", state.options.matching.case.name()));
+    }
+
+    if !state.scope_stack.is_empty() {
+        counter.push_str(&format!(" [scope {}]", state.scope_stack.len()));
+    }
+
+    if let Some((message, _)) = &state.status_message {
+        counter.push_str(&format!("  {message}"));
+    }
+
+    let counter_style = if state.options.color {
+        Style::new().dim()
+    } else {
+        Style::new()
+    };
+
+    let counter = Paragraph::new(Span::styled(counter, counter_style));
+
+    f.render_widget(counter, counter_area);
+
+    // === Draw results list === //
+
+    // The list offset is managed here (not by the list widget) so that only
+    // the visible window of items ever needs to be materialized: follow the
+    // selection, keeping `--scroll-off` rows of context around it
+    let visible_height = usize::from(results_area.height).max(1);
+
+    {
+        let bound = state.filtered.len().saturating_sub(visible_height);
+        let mut offset = state.list_state.offset().min(bound);
+
+        if let Some(selected) = state.list_state.selected() {
+            // A margin bigger than the viewport would fight itself
+            let scroll_off = state
+                .options
+                .scroll_off
+                .min(visible_height.saturating_sub(1) / 2);
+
+            let max_offset = selected.saturating_sub(scroll_off);
+            let min_offset = (selected + scroll_off + 1).saturating_sub(visible_height);
+
+            offset = offset.clamp(min_offset, max_offset.max(min_offset)).min(bound);
+        }
+
+        *state.list_state.offset_mut() = offset;
+    }
+
+    // With `--columns`, compute each column's width over the visible
+    // candidates so their fields can be padded into alignment
+    let column_widths = if state.options.columns {
+        let delimiter = state.options.matching.delimiter.as_deref();
+        let mut widths: Vec<usize> = vec![];
+
+        let visible = state
+            .filtered
+            .iter()
+            .skip(state.list_state.offset())
+            .take(visible_height);
+
+        for entry in visible {
+            let text = entry
+                .transformed
+                .as_deref()
+                .unwrap_or(&state.list[entry.original_index]);
+
+            for (i, (_, field)) in split_fields(text, delimiter).iter().enumerate() {
+                let width = field.as_str().width();
+
+                match widths.get_mut(i) {
+                    Some(max) => *max = (*max).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+
+        widths
+    } else {
+        vec![]
+    };
+
+    // Rendered height of each item, for mouse hit-testing when `--wrap`
+    // makes items span several rows
+    let mut item_heights = vec![];
+
+    // Only the visible slice of results is turned into list items; off-screen
+    // entries would be built and thrown away on every frame otherwise
+    let results = state
+        .filtered
+        .iter()
+        .enumerate()
+        .skip(state.list_state.offset())
+        .take(visible_height)
+        .map(|(row, entry)| {
+            let mut line = state.render_line(entry);
+
+            if state.options.columns {
+                line = align_line_columns(
+                    line,
+                    state.options.matching.delimiter.as_deref(),
+                    &column_widths,
+                );
+            }
+
+            // Marker column showing which entries are part of the
+            // multi-selection (only rendered, never part of the entry's text)
+            if state.options.multi {
+                let marker = if state.marked.contains(&entry.original_index) {
+                    let style = if state.options.color {
+                        Style::new().fg(state.options.theme.pointer)
+                    } else {
+                        Style::new()
+                    };
+
+                    Span::styled("* ".to_owned(), style)
+                } else {
+                    Span::raw("  ".to_owned())
+                };
+
+                line.spans.insert(0, marker);
+            }
+
+            let target = usize::from(results_area.width);
+
+            // Pad rows to the pane width so the selected-row highlight spans
+            // the whole line instead of stopping at the text, and apply the
+            // optional zebra stripe to every other entry (the selected-row
+            // highlight is patched on top by the list, so it still dominates)
+            let pad = |mut line: Line<'static>| {
+                let width = line.width();
+
+                if width < target {
+                    line.spans.push(Span::raw(" ".repeat(target - width)));
+                }
+
+                if state.options.color && state.options.zebra && row % 2 == 1 {
+                    for span in &mut line.spans {
+                        span.style = span.style.patch(Style::new().bg(Color::Indexed(236)));
+                    }
+                }
+
+                line
+            };
+
+            // Multi-line records render across several rows, with a dim
+            // separator line closing each record
+            if state.options.read_records.is_some() {
+                let mut rows = split_line_at_newlines(line)
+                    .into_iter()
+                    .map(&pad)
+                    .collect::<Vec<_>>();
+
+                let separator_style = if state.options.color {
+                    Style::new().dim()
+                } else {
+                    Style::new()
+                };
+
+                rows.push(Line::from(Span::styled(
+                    "─".repeat(target),
+                    separator_style,
+                )));
+
+                item_heights.push(rows.len());
+
+                return ListItem::new(Text::from(rows));
+            }
+
+            // With `--wrap`, long items flow onto several rows; the default
+            // applies the horizontal scroll and truncates with an indicator
+            if state.options.wrap {
+                let rows = wrap_line(line, target)
+                    .into_iter()
+                    .map(pad)
+                    .collect::<Vec<_>>();
+
+                item_heights.push(rows.len());
+
+                ListItem::new(Text::from(rows))
+            } else {
+                line = scroll_line_left(line, state.h_scroll);
+                line = trim_line_to_width(line, target);
+
+                ListItem::new(pad(line))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    state.item_heights = item_heights;
+
+    // Without colors, reverse video keeps the selection visible on a
+    // monochrome terminal
+    let highlight_style = if state.options.color {
+        Style::default().bg(state.options.theme.selected_bg)
+    } else {
+        Style::default().add_modifier(Modifier::REVERSED)
+    };
+
+    let mut results = List::new(results)
+        .highlight_style(highlight_style)
+        .highlight_symbol("> ");
+
+    if state.options.layout.list_bottom_anchored() {
+        results = results.start_corner(Corner::BottomLeft);
+    }
+
+    state.results_area = Some(results_area);
+
+    // The items only cover the visible window, so the widget renders with a
+    // window-relative state while `state.list_state` keeps the absolute one
+    let mut window_state = ListState::default();
+
+    window_state.select(
+        state
+            .list_state
+            .selected()
+            .map(|selected| selected.saturating_sub(state.list_state.offset())),
+    );
+
+    f.render_stateful_widget(results, results_area, &mut window_state);
+
+    // === Draw help overlay === //
+
+    if state.show_help {
+        draw_help_overlay(f, state);
+    }
+}
+
+/// Default key bindings listed in the help overlay
+const HELP_BINDINGS: &str = "\
+enter            accept
+esc / ctrl-c     abort
+up / down        move the selection
+page-up / -down  move by a page
+ctrl-home / -end jump to first / last
+tab              toggle selection (--multi)
+alt-a / -d / -i  select / deselect / invert all
+ctrl-s           toggle sorting
+alt-c            cycle case sensitivity
+ctrl-y           copy to clipboard
+shift-up / -down scroll the preview
+ctrl-a / ctrl-e  cursor to start / end
+ctrl-u / ctrl-k  delete to start / end
+ctrl-w           delete previous word
+ctrl-t / ctrl-b  push / pop search scope
+?                toggle this help (empty query)";
+
+/// Render the help overlay as a centered popup listing all key bindings,
+/// including `--bind` customizations
+fn draw_help_overlay<B: Backend>(f: &mut Frame<B>, state: &State) {
+    let mut text = HELP_BINDINGS.to_owned();
+
+    if !state.options.bindings.is_empty() {
+        text.push_str("\n\nCustom bindings:");
+
+        let mut bindings = state
+            .options
+            .bindings
+            .iter()
+            .map(|(combo, action)| format!("\n{:16} {}", format_key_combo(*combo), action.name()))
+            .collect::<Vec<_>>();
+        bindings.sort();
+
+        for binding in bindings {
+            text.push_str(&binding);
+        }
+    }
+
+    let height = (text.lines().count() as u16 + 2).min(f.size().height);
+    let width = 44.min(f.size().width);
+
+    let area = Rect {
+        x: (f.size().width - width) / 2,
+        y: (f.size().height - height) / 2,
+        width,
+        height,
+    };
+
+    f.render_widget(Clear, area);
+
+    let help = Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(" Help "));
+
+    f.render_widget(help, area);
+}
+
+/// Format a key combination the way `--bind` specs spell it
+fn format_key_combo((code, modifiers): (KeyCode, KeyModifiers)) -> String {
+    let mut spec = String::new();
+
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        spec.push_str("ctrl-");
+    }
+
+    if modifiers.contains(KeyModifiers::ALT) {
+        spec.push_str("alt-");
+    }
+
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        spec.push_str("shift-");
+    }
+
+    let key = match code {
+        KeyCode::Enter => "enter".to_owned(),
+        KeyCode::Esc => "esc".to_owned(),
+        KeyCode::Tab => "tab".to_owned(),
+        KeyCode::Char(' ') => "space".to_owned(),
+        KeyCode::Up => "up".to_owned(),
+        KeyCode::Down => "down".to_owned(),
+        KeyCode::Left => "left".to_owned(),
+        KeyCode::Right => "right".to_owned(),
+        KeyCode::Home => "home".to_owned(),
+        KeyCode::End => "end".to_owned(),
+        KeyCode::PageUp => "page-up".to_owned(),
+        KeyCode::PageDown => "page-down".to_owned(),
+        KeyCode::Backspace => "backspace".to_owned(),
+        KeyCode::Delete => "delete".to_owned(),
+        KeyCode::Char(c) => c.to_string(),
+        _ => "?".to_owned(),
+    };
+
+    spec + &key
+}
+
+
+struct State {
+    options: Options,
+    input_widget: Input,
+    list: Vec<String>,
+
+    /// Entries still streaming in from the stdin reader thread
+    input_rx: mpsc::Receiver<String>,
+
+    /// Per-character styling of each candidate, parsed from its ANSI escape
+    /// sequences (only populated with `--ansi`)
+    ansi_styles: Vec<Vec<Style>>,
+
+    /// Entries already ingested, for `--unique` deduplication
+    seen: HashSet<String>,
+
+    /// Input lines captured by `--header-lines`, pinned above the results
+    input_header: Vec<String>,
+
+    /// Stream positions backing index-based output
+    stream_indices: StreamIndices,
+
+    /// Whether the stdin reader thread has exhausted its input
+    reading_complete: bool,
+
+    /// Current frame of the streaming spinner
+    spinner_frame: usize,
+    list_state: ListState,
+    filtered: Vec<FilteredEntry>,
+
+    /// Original indices of the entries marked in multi-select mode
+    marked: HashSet<usize>,
+
+    /// Query `filtered` was last computed for (`None` forces a refilter)
+    last_query: Option<String>,
+
+    /// Captured output of the preview command for the current selection
+    preview_text: String,
+
+    /// Original index of the entry `preview_text` was generated for
+    preview_for: Option<usize>,
+
+    /// Selection the preview is waiting to run for, and since when (for
+    /// debouncing)
+    preview_candidate: Option<usize>,
+    preview_candidate_since: Instant,
+
+    /// Number of lines the preview pane is scrolled down
+    preview_scroll: u16,
+
+    /// Whether the preview pane is currently hidden (runtime toggle)
+    preview_hidden: bool,
+
+    /// Area the preview pane was last rendered in, used to route mouse wheel
+    /// events (`None` when there is no preview)
+    preview_area: Option<Rect>,
+
+    /// Area the results list was last rendered in, used for mouse
+    /// hit-testing (`None` until the first draw)
+    results_area: Option<Rect>,
+
+    /// Transient message shown in the status line until it expires
+    status_message: Option<(String, Instant)>,
+
+    /// Whether the help overlay is currently shown
+    show_help: bool,
+
+    /// Display columns the result lines are scrolled to the right
+    h_scroll: usize,
+
+    /// Rendered height of each filtered item (only filled with `--wrap`,
+    /// where items can span several rows)
+    item_heights: Vec<usize>,
+
+    /// Previous search scopes saved by the drill-down feature, restored in
+    /// LIFO order
+    scope_stack: Vec<Scope>,
+}
+
+/// A search scope saved by the drill-down feature
+struct Scope {
+    list: Vec<String>,
+    ansi_styles: Vec<Vec<Style>>,
+
+    /// Stream positions of the scope's candidates
+    kept: Vec<usize>,
+}
+
+/// A filtered result as displayed in the list; its styled line is only
+/// built at draw time, and only for the visible window
+struct FilteredEntry {
+    /// Position of the entry in the original input list
+    original_index: usize,
+
+    /// Display text when a transform (`--with-nth`) rebuilt it; the original
+    /// line is used otherwise, avoiding a clone per match
+    transformed: Option<String>,
+
+    /// Character positions highlighted as matched
+    matched_positions: Vec<usize>,
+}
+
+impl State {
+    /// Re-run the filter against the current query and re-anchor the
+    /// selection
+    fn refilter(&mut self) {
+        // Remember which underlying line is selected, so the highlight can
+        // stay on it after refiltering reshuffles the indices
+        let anchored = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.filtered.get(selected))
+            .map(|entry| entry.original_index);
+
+        let query = self.input_widget.value();
+
+        // Typing more characters can only narrow the results: when the new
+        // query merely extends the previous one, only the previous matches
+        // need re-scoring. That assumption breaks for negated terms (which
+        // widen as they grow), in regex mode (`foo` → `foo|bar` widens),
+        // when the previous query ended on a `$` anchor (extending `ab$`
+        // to `ab$c` turns the anchor into a literal), and for non-subsequence
+        // scorers (the naive one scores per character, so `ab` → `abz` can
+        // turn a no-match into a match) — all of those fall back to a full
+        // scan.
+        let subset = match &self.last_query {
+            Some(last)
+                if !last.is_empty()
+                    && query.starts_with(last.as_str())
+                    && !query.contains('!')
+                    && !self.options.matching.regex
+                    && self.options.matching.algorithm == Algorithm::Subsequence
+                    && !last.trim_end().ends_with('$') =>
+            {
+                let mut indices = self
+                    .filtered
+                    .iter()
+                    .map(|entry| entry.original_index)
+                    .collect::<Vec<_>>();
+
+                // Keep the ascending original order so tie-breaking behaves
+                // exactly like a full scan
+                indices.sort_unstable();
+
+                Some(indices)
+            }
+
+            _ => None,
+        };
+
+        let filtered = match subset {
+            Some(indices) => {
+                let sublist = indices
+                    .iter()
+                    .map(|i| self.list[*i].clone())
+                    .collect::<Vec<_>>();
+
+                fuzzy_find(query, &sublist, &self.options.matching)
+                    .into_iter()
+                    .map(|mut result| {
+                        result.original_index = indices[result.original_index];
+                        result
+                    })
+                    .collect()
+            }
+
+            None => fuzzy_find(query, &self.list, &self.options.matching),
+        };
+
+        // An uncompilable pattern just matches nothing, but say why
+        if self.options.matching.regex && !query.is_empty() && Regex::new(query).is_err() {
+            self.set_status("Invalid regex");
+        }
+
+        self.filtered = filtered
+            .into_iter()
+            .map(|result| FilteredEntry {
+                original_index: result.original_index,
+                transformed: result.transformed,
+                matched_positions: result.matched_positions,
+            })
+            .collect::<Vec<_>>();
+
+        let anchored_position = anchored.and_then(|original_index| {
+            self.filtered
+                .iter()
+                .position(|entry| entry.original_index == original_index)
+        });
+
+        match (anchored_position, self.list_state.selected()) {
+            // The previously selected line is still in the results: follow it
+            (Some(position), _) => self.list_state.select(Some(position)),
+
+            // Drop the selection entirely when the query filters every
+            // candidate out, so Enter is a no-op instead of panicking
+            (None, Some(_)) if self.filtered.is_empty() => self.list_state.select(None),
+
+            (None, Some(selected)) => {
+                if selected >= self.filtered.len() {
+                    self.list_state.select(Some(self.filtered.len() - 1));
+                }
+            }
+
+            (None, None) => {
+                if !self.filtered.is_empty() {
+                    // Where the cursor starts is configurable: history-style
+                    // pickers often want the most relevant item at the
+                    // bottom
+                    let initial = if self.options.select_last {
+                        self.filtered.len() - 1
+                    } else {
+                        0
+                    };
+
+                    self.list_state.select(Some(initial));
+                }
+            }
+        }
+    }
+
+    /// Original index and text of the currently selected result, if any
+    /// (always the full original line, even when the displayed text is
+    /// transformed by `--with-nth`)
+    fn selected_entry(&self) -> Option<(usize, String)> {
+        let selected = self.list_state.selected()?;
+
+        self.filtered.get(selected).map(|entry| {
+            (
+                self.stream_index(entry.original_index),
+                self.list[entry.original_index].clone(),
+            )
+        })
+    }
+
+    /// Map a click position to the index of the result row it landed on,
+    /// accounting for the list's scroll offset
+    fn hit_test_result(&self, column: u16, row: u16) -> Option<usize> {
+        let area = self.results_area?;
+
+        if column < area.x || column >= area.x + area.width || row < area.y {
+            return None;
+        }
+
+        if row >= area.y + area.height {
+            return None;
+        }
+
+        // In reverse layout the list is anchored to the bottom, so rows count
+        // up from the last line of the area instead
+        let row_in_area = if self.options.layout.list_bottom_anchored() {
+            usize::from(area.y + area.height - 1 - row)
+        } else {
+            usize::from(row.checked_sub(area.y)?)
+        };
+
+        // With `--wrap` or multi-line records, items span several rows: walk
+        // the rendered heights to find which item the row falls into
+        let index = if self.options.wrap || self.options.read_records.is_some() {
+            let mut remaining = row_in_area;
+            let mut index = self.list_state.offset();
+
+            loop {
+                // Heights are recorded for the visible window only
+                let height = self
+                    .item_heights
+                    .get(index - self.list_state.offset())
+                    .copied()
+                    .unwrap_or(1);
+
+                if remaining < height {
+                    break index;
+                }
+
+                remaining -= height;
+                index += 1;
+            }
+        } else {
+            self.list_state.offset() + row_in_area
+        };
+
+        // The list may be shorter than the viewport: ignore clicks past it
+        (index < self.filtered.len()).then_some(index)
+    }
+
+    /// Move the selection one result up, saturating at the top
+    fn select_previous(&mut self) {
+        self.move_selection_up(1);
+    }
+
+    /// Move the selection one result down, saturating at the bottom
+    fn select_next(&mut self) {
+        self.move_selection_down(1);
+    }
+
+    /// Jump the selection to the first result
+    fn select_first(&mut self) {
+        if !self.filtered.is_empty() {
+            self.list_state.select(Some(0));
+        }
+    }
+
+    /// Jump the selection to the last result
+    fn select_last(&mut self) {
+        if let Some(last) = self.filtered.len().checked_sub(1) {
+            self.list_state.select(Some(last));
+        }
+    }
+
+    /// Move the selection `step` results up, saturating at the top (or
+    /// wrapping around to the bottom with `--cycle`)
+    fn move_selection_up(&mut self, step: usize) {
+        let Some(last) = self.filtered.len().checked_sub(1) else {
+            return;
+        };
+
+        match self.list_state.selected() {
+            Some(0) if self.options.cycle => self.list_state.select(Some(last)),
+            Some(selected) => self.list_state.select(Some(selected.saturating_sub(step))),
+            None => self.list_state.select(Some(last)),
+        }
+    }
+
+    /// Move the selection `step` results down, saturating at the bottom (or
+    /// wrapping around to the top with `--cycle`)
+    fn move_selection_down(&mut self, step: usize) {
+        let Some(last) = self.filtered.len().checked_sub(1) else {
+            return;
+        };
+
+        match self.list_state.selected() {
+            Some(selected) if selected == last && self.options.cycle => {
+                self.list_state.select(Some(0))
+            }
+            Some(selected) => self.list_state.select(Some((selected + step).min(last))),
+            None => self.list_state.select(Some(0)),
+        }
+    }
+
+    /// Build the styled line for a filtered entry (matched characters
+    /// highlighted, the rest optionally dimmed, on top of any `--ansi`
+    /// styling carried by the input)
+    fn render_line(&self, entry: &FilteredEntry) -> Line<'static> {
+        let text = entry
+            .transformed
+            .as_deref()
+            .unwrap_or(&self.list[entry.original_index]);
+
+        let chars = text
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                // Styling carried by the input itself (with `--ansi`) is the
+                // base the match highlight is patched onto
+                let base = if self.options.color {
+                    self.ansi_styles
+                        .get(entry.original_index)
+                        .and_then(|styles| styles.get(i))
+                        .copied()
+                        .unwrap_or_default()
+                } else {
+                    Style::new()
+                };
+
+                let matched_style = if self.options.color {
+                    Style::new().bold().fg(self.options.theme.matched)
+                } else {
+                    Style::new().underlined()
+                };
+
+                // `matched_positions` is sorted by construction
+                if entry.matched_positions.binary_search(&i).is_ok() {
+                    Span::styled(c.to_string(), base.patch(matched_style))
+                } else if self.options.color
+                    && !self.options.no_dim
+                    && !entry.matched_positions.is_empty()
+                {
+                    // Subtly dim the characters that did *not* participate
+                    // in the match, so the matched ones pop
+                    Span::styled(c.to_string(), base.patch(Style::new().dim()))
+                } else {
+                    Span::styled(c.to_string(), base)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Line::from(chars)
+    }
+
+    /// Position of a candidate (by its index in `list`) in the original
+    /// input stream, which is what index-based output reports
+    fn stream_index(&self, list_index: usize) -> usize {
+        self.stream_indices
+            .kept
+            .get(list_index)
+            .copied()
+            .unwrap_or(list_index)
+    }
+
+    /// Show a transient message in the status line
+    fn set_status(&mut self, message: impl Into<String>) {
+        self.status_message = Some((message.into(), Instant::now()));
+    }
+
+    /// Whether a point is inside the rendered preview pane
+    fn preview_contains(&self, column: u16, row: u16) -> bool {
+        self.preview_area.is_some_and(|area| {
+            column >= area.x
+                && column < area.x + area.width
+                && row >= area.y
+                && row < area.y + area.height
+        })
+    }
+
+    /// Scroll the preview pane up, saturating at the top
+    fn scroll_preview_up(&mut self, step: u16) {
+        self.preview_scroll = self.preview_scroll.saturating_sub(step);
+    }
+
+    /// Scroll the preview pane down, clamped to its line count
+    fn scroll_preview_down(&mut self, step: u16) {
+        let max = self.preview_text.lines().count().saturating_sub(1) as u16;
+
+        self.preview_scroll = (self.preview_scroll.saturating_add(step)).min(max);
+    }
+
+    /// Number of visible result rows, used as the Page Up / Page Down step
+    /// (adapts to the terminal size since the area is refreshed on each draw)
+    fn page_size(&self) -> usize {
+        self.results_area
+            .map_or(10, |area| usize::from(area.height))
+            .max(1)
+    }
+}
+
+/// Colors used across the UI, overridable with `--colors` or the
+/// `QUICKFUZZ_COLORS` environment variable (e.g.
+/// `prompt=blue,matched=#ff00ff,selected-bg=238,pointer=green`)
+struct Theme {
+    /// Prompt prefix before the search box
+    prompt: Color,
+
+    /// Characters that participated in the match
+    matched: Color,
+
+    /// Background of the selected row
+    selected_bg: Color,
+
+    /// Multi-select marker column
+    pointer: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        // Defaults picked to stay visible on both dark and light backgrounds
+        Self {
+            prompt: Color::Blue,
+            matched: Color::Cyan,
+            selected_bg: Color::DarkGray,
+            pointer: Color::Magenta,
+        }
+    }
+}
+
+impl Theme {
+    /// Apply a comma-separated list of `NAME=COLOR` assignments
+    fn apply_spec(&mut self, spec: &str) -> Result<(), String> {
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            let (name, color) = part.split_once('=').ok_or_else(|| {
+                format!("Invalid color assignment (expected NAME=COLOR): {part}")
+            })?;
+
+            let color = parse_color(color)?;
+
+            match name {
+                "prompt" => self.prompt = color,
+                "matched" => self.matched = color,
+                "selected-bg" => self.selected_bg = color,
+                "pointer" => self.pointer = color,
+
+                _ => return Err(format!("Unknown color name: {name}")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a color name, a 256-color index, or a `#rrggbb` truecolor value
+fn parse_color(value: &str) -> Result<Color, String> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let parsed = (hex.len() == 6)
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .ok_or_else(|| format!("Invalid hex color: {value}"))?;
+
+        return Ok(Color::Rgb(
+            (parsed >> 16) as u8,
+            (parsed >> 8) as u8,
+            parsed as u8,
+        ));
+    }
+
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Color::Indexed(index));
+    }
+
+    match value {
+        "black" => Ok(Color::Black),
+        "red" => Ok(Color::Red),
+        "green" => Ok(Color::Green),
+        "yellow" => Ok(Color::Yellow),
+        "blue" => Ok(Color::Blue),
+        "magenta" => Ok(Color::Magenta),
+        "cyan" => Ok(Color::Cyan),
+        "white" => Ok(Color::White),
+        "gray" | "grey" => Ok(Color::Gray),
+        "dark-gray" | "dark-grey" => Ok(Color::DarkGray),
+
+        _ => Err(format!("Unknown color: {value}")),
+    }
+}
+
+/// Which side of the screen the preview pane takes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PreviewPosition {
+    Right,
+    Down,
+}
+
+/// Placement and size of the preview pane, as configured by
+/// `--preview-window` specs like `right:50%`, `down:10` or `hidden`
+struct PreviewWindow {
+    position: PreviewPosition,
+    size: Height,
+
+    /// Start with the pane hidden (toggleable at runtime)
+    hidden: bool,
+}
+
+impl Default for PreviewWindow {
+    fn default() -> Self {
+        Self {
+            position: PreviewPosition::Right,
+            size: Height::Percent(50),
+            hidden: false,
+        }
+    }
+}
+
+impl PreviewWindow {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut window = Self::default();
+
+        for part in spec.split(':').filter(|part| !part.is_empty()) {
+            match part {
+                "right" => window.position = PreviewPosition::Right,
+                "down" => window.position = PreviewPosition::Down,
+                "hidden" => window.hidden = true,
+
+                size => {
+                    window.size = Height::parse(size)
+                        .map_err(|_| format!("Invalid preview window spec: {spec}"))?;
+                }
+            }
+        }
+
+        Ok(window)
+    }
+}
+
+/// Arrangement of the prompt and results list, mirroring fzf's `--layout`
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum LayoutMode {
+    /// Prompt on top, results growing downward
+    #[default]
+    Default,
+
+    /// Prompt at the bottom, results anchored just above it and growing
+    /// upward
+    Reverse,
+
+    /// Prompt on top, but results anchored to the bottom of the screen and
+    /// growing upward toward it
+    ReverseList,
+}
+
+impl LayoutMode {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "default" => Ok(Self::Default),
+            "reverse" => Ok(Self::Reverse),
+            "reverse-list" => Ok(Self::ReverseList),
+
+            _ => Err(format!("Unknown layout: {name}")),
+        }
+    }
+
+    /// Whether the prompt (and counter) sit below the results
+    fn prompt_at_bottom(self) -> bool {
+        self == Self::Reverse
+    }
+
+    /// Whether the results list is anchored to the bottom of its area
+    fn list_bottom_anchored(self) -> bool {
+        matches!(self, Self::Reverse | Self::ReverseList)
+    }
+}
+
+/// Command-line options
+struct Options {
+    /// UI color theme
+    theme: Theme,
+    /// Options forwarded to the matcher
+    matching: MatchOptions,
+
+    /// Allow marking multiple entries with Tab and accepting them all at once
+    multi: bool,
+
+    /// Print the 0-based index of the chosen line(s) in the original input
+    /// instead of their text
+    print_index: bool,
+
+    /// Print the final query on its own line before the selection(s)
+    print_query: bool,
+
+    /// Split stdin on NUL bytes instead of newlines
+    read0: bool,
+
+    /// Terminate each printed selection with a NUL byte
+    print0: bool,
+
+    /// Split stdin into (possibly multi-line) records on this separator
+    /// string instead of reading line by line
+    read_records: Option<String>,
+
+    /// Initial content of the search box
+    query: String,
+
+    /// Prompt string rendered before the search box
+    prompt: String,
+
+    /// Arrangement of the prompt and results list
+    layout: LayoutMode,
+
+    /// Start with the cursor on the last result instead of the first
+    select_last: bool,
+
+    /// Render inline on this many lines instead of the alternate screen
+    height: Option<Height>,
+
+    /// Print the ranked matches for this query to stdout instead of opening
+    /// the TUI
+    filter: Option<String>,
+
+    /// Skip the TUI when the initial query matches exactly one candidate
+    select_1: bool,
+
+    /// Exit with an error when the initial query matches nothing
+    exit_0: bool,
+
+    /// How long to wait for an event before redrawing anyway
+    tick_rate: Duration,
+
+    /// Parse ANSI color codes in the input instead of matching and showing
+    /// them as literal characters
+    ansi: bool,
+
+    /// Drop exact-duplicate input lines, keeping the first occurrence
+    unique: bool,
+
+    /// Trim surrounding whitespace from each input line
+    trim: bool,
+
+    /// Drop input lines that are empty (after trimming, if enabled)
+    skip_empty: bool,
+
+    /// Shell command whose output is shown in a side pane for the current
+    /// selection (`{}` is substituted with the selected entry)
+    preview: Option<String>,
+
+    /// Placement and size of the preview pane
+    preview_window: PreviewWindow,
+
+    /// Wrap long result lines onto several rows instead of truncating them
+    wrap: bool,
+
+    /// Give every other result row a slightly different background
+    zebra: bool,
+
+    /// Align the displayed fields (split by `--delimiter`) into padded
+    /// columns
+    columns: bool,
+
+    /// Don't dim the non-matching portion of each result
+    no_dim: bool,
+
+    /// Whether ANSI styling may be used at all (resolved from `NO_COLOR` and
+    /// `--color=never|auto|always`; a monochrome fallback is used otherwise)
+    color: bool,
+
+    /// Don't capture the mouse, keeping the terminal's own text selection
+    /// usable
+    no_mouse: bool,
+
+    /// File recording accepted entries, used to boost frequently/recently
+    /// picked items
+    history: Option<PathBuf>,
+
+    /// Print the outcome as a JSON object instead of raw lines
+    json: bool,
+
+    /// Pre-mark candidates containing this pattern on startup (implies
+    /// `--multi`)
+    marked: Option<String>,
+
+    /// Reverse the input order after reading it (newest-first for history)
+    tac: bool,
+
+    /// Wrap the selection around when moving past the first or last result
+    cycle: bool,
+
+    /// Minimum number of result rows kept visible above and below the
+    /// selection while scrolling
+    scroll_off: usize,
+
+    /// Extra keys that accept the selection, reported back on their own
+    /// output line (stored with their original spec for printing)
+    expect: Vec<((KeyCode, KeyModifiers), String)>,
+
+    /// Custom key bindings, taking precedence over the default ones
+    bindings: HashMap<(KeyCode, KeyModifiers), Action>,
+
+    /// Fixed informational line(s) pinned above the results, never matched
+    /// nor selectable
+    header: Option<String>,
+
+    /// Treat the first N input lines as a pinned header instead of
+    /// candidates (for tabular input like `ps`)
+    header_lines: usize,
+
+}
+
+/// Height requested with `--height`, either absolute or relative to the
+/// terminal height
+enum Height {
+    Lines(u16),
+    Percent(u16),
+}
+
+impl Height {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|percent| (1..=100).contains(percent))
+                    .ok_or_else(|| format!("Invalid height percentage: {value}"))?;
+
+                Ok(Self::Percent(percent))
+            }
+
+            None => value
+                .parse::<u16>()
+                .map(Self::Lines)
+                .map_err(|_| format!("Invalid height value: {value}")),
+        }
+    }
+
+    /// Number of lines to use for the given terminal height, always leaving
+    /// room for the prompt and a couple of results (a terminal shorter than
+    /// that minimum caps it, instead of panicking on an inverted clamp)
+    fn resolve(&self, terminal_rows: u16) -> u16 {
+        let lines = match self {
+            Self::Lines(lines) => *lines,
+            Self::Percent(percent) => terminal_rows * percent / 100,
+        };
+
+        lines.min(terminal_rows).max(3.min(terminal_rows))
+    }
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            theme: Theme::default(),
+            matching: MatchOptions::default(),
+            multi: false,
+            print_index: false,
+            print_query: false,
+            read0: false,
+            print0: false,
+            read_records: None,
+            query: String::new(),
+            prompt: "> ".to_owned(),
+            layout: LayoutMode::default(),
+            select_last: false,
+            height: None,
+            filter: None,
+            select_1: false,
+            exit_0: false,
+            tick_rate: Duration::from_millis(100),
+            ansi: false,
+            unique: false,
+            trim: false,
+            skip_empty: false,
+            preview: None,
+            preview_window: PreviewWindow::default(),
+            wrap: false,
+            zebra: false,
+            columns: false,
+            no_dim: false,
+            color: true,
+            no_mouse: false,
+            history: std::env::var_os("QUICKFUZZ_HISTORY").map(PathBuf::from),
+            json: false,
+            marked: None,
+            tac: false,
+            cycle: false,
+            scroll_off: 0,
+            expect: vec![],
+            bindings: HashMap::new(),
+            header: None,
+            header_lines: 0,
+        };
+
+        // `--colors` assignments are applied on top, so flags win
+        if let Ok(spec) = std::env::var("QUICKFUZZ_COLORS") {
+            options.theme.apply_spec(&spec)?;
+        }
+
+        // The conventional opt-out, overridable with `--color=always`
+        let mut color_mode = if std::env::var_os("NO_COLOR").is_some() {
+            "never".to_owned()
+        } else {
+            "auto".to_owned()
+        };
+
+        while let Some(arg) = args.next() {
+            // Flags taking a value consume the next argument
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Argument {arg} requires a value"))
+            };
+
+            match arg.as_str() {
+                "--exact" | "-e" => options.matching.exact = true,
+                "--multi" | "-m" => options.multi = true,
+                "--print-index" => options.print_index = true,
+                "--print-query" => options.print_query = true,
+                "--read0" => options.read0 = true,
+                "--read-records" => options.read_records = Some(value()?),
+                "--print0" => options.print0 = true,
+                "--query" | "-q" => options.query = value()?,
+                "--prompt" => options.prompt = value()?,
+                "--reverse" => options.layout = LayoutMode::Reverse,
+                "--layout" => options.layout = LayoutMode::parse(&value()?)?,
+                "--select-last" => options.select_last = true,
+                "--select-first" => options.select_last = false,
+                "--height" => options.height = Some(Height::parse(&value()?)?),
+                "--filter" | "-f" => options.filter = Some(value()?),
+                "--select-1" | "-1" => options.select_1 = true,
+                "--exit-0" | "-0" => options.exit_0 = true,
+                "--no-sort" => options.matching.no_sort = true,
+                "--ansi" => options.ansi = true,
+                "--unique" | "-u" => options.unique = true,
+                "--trim" => options.trim = true,
+                "--skip-empty" => options.skip_empty = true,
+                "--preview" => options.preview = Some(value()?),
+                "--preview-window" => options.preview_window = PreviewWindow::parse(&value()?)?,
+                "--header" => options.header = Some(value()?),
+
+                "--algo" => options.matching.algorithm = Algorithm::parse(&value()?)?,
+
+                "--tiebreak" => {
+                    for name in value()?.split(',') {
+                        options.matching.tiebreak.push(Tiebreak::parse(name)?);
+                    }
+                }
+                "--normalize" => options.matching.normalize = true,
+                "--regex" => options.matching.regex = true,
+                "--wrap" => options.wrap = true,
+                "--zebra" => options.zebra = true,
+                "--columns" => options.columns = true,
+                "--no-dim" => options.no_dim = true,
+
+                "--color" => {
+                    let value = value()?;
+
+                    if !matches!(value.as_str(), "never" | "auto" | "always") {
+                        return Err(format!("Invalid color mode: {value}"));
+                    }
+
+                    color_mode = value;
+                }
+                "--no-mouse" => options.no_mouse = true,
+                "--history" => options.history = Some(PathBuf::from(value()?)),
+                "--json" => options.json = true,
+
+                "--marked" => {
+                    options.marked = Some(value()?);
+
+                    // A pre-marked set only makes sense with multi-select
+                    options.multi = true;
+                }
+                "--tac" => options.tac = true,
+                "--cycle" => options.cycle = true,
+                "--colors" => options.theme.apply_spec(&value()?)?,
+
+                "--scroll-off" => {
+                    let value = value()?;
+
+                    options.scroll_off = value
+                        .parse()
+                        .map_err(|_| format!("Invalid scroll margin: {value}"))?;
+                }
+
+                "--delimiter" | "-d" => options.matching.delimiter = Some(value()?),
+
+                "--nth" => {
+                    for spec in value()?.split(',') {
+                        options.matching.nth.push(FieldRange::parse(spec)?);
+                    }
+                }
+
+                "--with-nth" => {
+                    for spec in value()?.split(',') {
+                        options.matching.with_nth.push(FieldRange::parse(spec)?);
+                    }
+                }
+
+                "--header-lines" => {
+                    let value = value()?;
+
+                    options.header_lines = value
+                        .parse()
+                        .map_err(|_| format!("Invalid header line count: {value}"))?;
+                }
+
+                "--expect" => {
+                    for spec in value()?.split(',') {
+                        options.expect.push((parse_key_combo(spec)?, spec.to_owned()));
+                    }
+                }
+
+                "--bind" => {
+                    for spec in split_bind_specs(&value()?) {
+                        let (key, action) = spec.split_once(':').ok_or_else(|| {
+                            format!("Invalid binding (expected KEY:ACTION): {spec}")
+                        })?;
+
+                        options
+                            .bindings
+                            .insert(parse_key_combo(key)?, Action::parse(action)?);
+                    }
+                }
+
+                "--tick-rate" => {
+                    let value = value()?;
+
+                    let millis = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|millis| *millis > 0)
+                        .ok_or_else(|| format!("Invalid tick rate (in ms): {value}"))?;
+
+                    options.tick_rate = Duration::from_millis(millis);
+                }
+
+                _ => return Err(format!("Unknown argument: {arg}")),
+            }
+        }
+
+        options.color = match color_mode.as_str() {
+            "never" => false,
+            "always" => true,
+            _ => io::stdout().is_terminal(),
+        };
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only used by the library's test suite and the benchmark harness
+    use criterion as _;
+    use proptest as _;
+    use ratatui::backend::TestBackend;
+
+    fn test_state(list: Vec<String>) -> State {
+        let filtered = list
+            .iter()
+            .enumerate()
+            .map(|(i, _)| FilteredEntry {
+                original_index: i,
+                transformed: None,
+                matched_positions: vec![],
+            })
+            .collect();
+
+        let (_, input_rx) = mpsc::channel();
+
+        State {
+            options: Options::parse(std::iter::empty()).unwrap(),
+            input_widget: Input::default(),
+            list,
+            input_rx,
+            ansi_styles: vec![],
+            seen: HashSet::new(),
+            input_header: vec![],
+            stream_indices: StreamIndices::default(),
+            reading_complete: true,
+            spinner_frame: 0,
+            list_state: ListState::default(),
+            filtered,
+            marked: HashSet::new(),
+            last_query: None,
+            preview_text: String::new(),
+            preview_for: None,
+            preview_candidate: None,
+            preview_candidate_since: Instant::now(),
+            preview_scroll: 0,
+            preview_hidden: false,
+            preview_area: None,
+            results_area: None,
+            status_message: None,
+            show_help: false,
+            h_scroll: 0,
+            item_heights: vec![],
+            scope_stack: vec![],
+        }
+    }
+
+    fn render(state: &mut State, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+
+        terminal.draw(|f| draw_ui(f, state)).unwrap();
+
+        terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol.as_str())
+            .collect()
+    }
+
+    fn ingest(options: &Options, entries: &[&str]) -> (Vec<String>, StreamIndices) {
+        let mut list = vec![];
+        let mut ansi_styles = vec![];
+        let mut seen = HashSet::new();
+        let mut input_header = vec![];
+        let mut stream_indices = StreamIndices::default();
+
+        for entry in entries {
+            push_entry(
+                options,
+                (*entry).to_owned(),
+                &mut list,
+                &mut ansi_styles,
+                &mut seen,
+                &mut input_header,
+                &mut stream_indices,
+            );
+        }
+
+        (list, stream_indices)
+    }
+
+    #[test]
+    fn backspace_on_an_empty_query_is_a_no_op() {
+        let mut input = Input::default();
+
+        input.handle_event(&Event::Key(KeyEvent::new(
+            KeyCode::Backspace,
+            KeyModifiers::NONE,
+        )));
+
+        assert_eq!(input.value(), "");
+        assert_eq!(input.cursor(), 0);
+    }
+
+    #[test]
+    fn only_plain_editing_keys_reach_the_input_widget() {
+        // Plain characters and deletions edit the query...
+        assert!(is_text_editing_key(&KeyEvent::new(
+            KeyCode::Char('x'),
+            KeyModifiers::NONE,
+        )));
+        assert!(is_text_editing_key(&KeyEvent::new(
+            KeyCode::Char('X'),
+            KeyModifiers::SHIFT,
+        )));
+        assert!(is_text_editing_key(&KeyEvent::new(
+            KeyCode::Delete,
+            KeyModifiers::NONE,
+        )));
+
+        // ...but unbound control combinations and function keys must not be
+        // inserted as literal text
+        assert!(!is_text_editing_key(&KeyEvent::new(
+            KeyCode::Char('x'),
+            KeyModifiers::CONTROL,
+        )));
+        assert!(!is_text_editing_key(&KeyEvent::new(
+            KeyCode::Char('x'),
+            KeyModifiers::ALT,
+        )));
+        assert!(!is_text_editing_key(&KeyEvent::new(
+            KeyCode::F(5),
+            KeyModifiers::NONE,
+        )));
+    }
+
+    #[test]
+    fn stream_indices_point_at_the_original_input_positions() {
+        let mut options = Options::parse(std::iter::empty()).unwrap();
+        options.unique = true;
+        options.skip_empty = true;
+        options.header_lines = 1;
+
+        let (list, stream_indices) = ingest(&options, &["HDR", "a", "", "b", "a", "c"]);
+
+        // The header consumed position 0, the empty line position 2 and the
+        // duplicate "a" position 4
+        assert_eq!(list, vec!["a", "b", "c"]);
+        assert_eq!(stream_indices.kept, vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn stream_indices_follow_a_tac_reversal() {
+        let options = Options::parse(std::iter::empty()).unwrap();
+
+        let (mut list, mut stream_indices) = ingest(&options, &["a", "b", "c"]);
+        let mut ansi_styles = vec![];
+
+        apply_tac(&mut list, &mut ansi_styles, &mut stream_indices);
+
+        assert_eq!(list, vec!["c", "b", "a"]);
+        assert_eq!(stream_indices.kept, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn index_output_reports_stream_positions_after_tac_filtering() {
+        let options = Options::parse(std::iter::empty()).unwrap();
+
+        let (mut list, mut stream_indices) = ingest(&options, &["a", "b", "c"]);
+        let mut ansi_styles = vec![];
+
+        apply_tac(&mut list, &mut ansi_styles, &mut stream_indices);
+
+        // This is exactly what the `--filter --print-index` output path does:
+        // map each match's list index back to its stream position
+        let indices = fuzzy_find("c", &list, &options.matching)
+            .into_iter()
+            .map(|result| stream_indices.kept[result.original_index])
+            .collect::<Vec<_>>();
+
+        assert_eq!(indices, vec![2]);
+    }
+
+    #[test]
+    fn selection_far_down_the_list_is_scrolled_into_view() {
+        let list = (1..=100).map(|i| format!("item{i}")).collect::<Vec<_>>();
+
+        let mut state = test_state(list);
+        state.list_state.select(Some(79));
+
+        let rendered = render(&mut state, 40, 12);
+
+        assert!(
+            rendered.contains("item80"),
+            "selected item should be visible: {rendered}"
+        );
+    }
 }
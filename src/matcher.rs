@@ -0,0 +1,1182 @@
+//! The crate's core: scoring and ranking candidates against a query,
+//! independent from any terminal concern.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use regex::Regex;
+
+/// Options influencing how candidates are matched and ranked
+#[derive(Default)]
+pub struct MatchOptions {
+    /// Only match candidates containing the query as a contiguous substring
+    pub exact: bool,
+
+    /// Keep matches in the original input order instead of sorting by score
+    pub no_sort: bool,
+
+    /// Literal delimiter splitting each line into fields (runs of whitespace
+    /// when unset)
+    pub delimiter: Option<String>,
+
+    /// Restrict matching to these fields (whole line when empty)
+    pub nth: Vec<FieldRange>,
+
+    /// Rebuild the displayed (and matched) text from these fields
+    pub with_nth: Vec<FieldRange>,
+
+    /// Scoring algorithm used for non-exact terms
+    pub algorithm: Algorithm,
+
+    /// How character case is handled while matching
+    pub case: CaseMode,
+
+    /// Strip diacritics from both query and candidates before matching, so
+    /// e.g. "cafe" matches "café"
+    pub normalize: bool,
+
+    /// Interpret the whole query as a regular expression instead of fuzzy
+    /// terms (an uncompilable pattern simply matches nothing)
+    pub regex: bool,
+
+    /// Flat score bonus added to specific candidates (keyed on their full
+    /// original text), e.g. the frecency boost from a history file
+    pub score_bonuses: HashMap<String, usize>,
+
+    /// Sort criteria applied in order (an empty list means the default
+    /// `score, length, index`); only applies to fuzzy matching, regex mode
+    /// has its own position-based ranking
+    pub tiebreak: Vec<Tiebreak>,
+}
+
+/// One sort criterion for `--tiebreak`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tiebreak {
+    /// Higher scores first
+    Score,
+
+    /// Shorter candidates first
+    Length,
+
+    /// Matches starting earlier in the candidate first
+    Begin,
+
+    /// Original input order
+    Index,
+}
+
+impl Tiebreak {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "score" => Ok(Self::Score),
+            "length" => Ok(Self::Length),
+            "begin" => Ok(Self::Begin),
+            "index" => Ok(Self::Index),
+
+            _ => Err(format!("Unknown tiebreak criterion: {name}")),
+        }
+    }
+}
+
+/// The criteria used when [`MatchOptions::tiebreak`] is left empty
+const DEFAULT_TIEBREAK: [Tiebreak; 3] = [Tiebreak::Score, Tiebreak::Length, Tiebreak::Index];
+
+/// How character case is handled while matching
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    /// Case-insensitive unless the query contains an uppercase character
+    #[default]
+    Smart,
+    Insensitive,
+    Sensitive,
+}
+
+impl CaseMode {
+    /// Next mode in the runtime toggle cycle
+    pub fn next(self) -> Self {
+        match self {
+            Self::Smart => Self::Insensitive,
+            Self::Insensitive => Self::Sensitive,
+            Self::Sensitive => Self::Smart,
+        }
+    }
+
+    /// Name shown in the status line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Smart => "smart",
+            Self::Insensitive => "insensitive",
+            Self::Sensitive => "sensitive",
+        }
+    }
+}
+
+/// Fold a character to its base form by dropping the combining marks of its
+/// canonical decomposition ("é" becomes "e"). The mapping is 1:1 per
+/// character, so match positions computed on folded text are valid positions
+/// in the original string.
+fn strip_diacritics(c: char) -> char {
+    let mut base = c;
+    let mut first = true;
+
+    unicode_normalization::char::decompose_canonical(c, |decomposed| {
+        if first {
+            base = decomposed;
+            first = false;
+        }
+    });
+
+    base
+}
+
+/// Apply [`strip_diacritics`] to a whole string
+fn strip_diacritics_str(text: &str) -> String {
+    text.chars().map(strip_diacritics).collect()
+}
+
+/// Case-fold a string strictly 1:1 per character (keeping only the first
+/// character of multi-character lowercase expansions, like 'İ' → "i̇").
+/// Unlike `str::to_lowercase`, this guarantees that character positions
+/// computed on the folded text are valid positions in the original string,
+/// which the highlight rendering relies on.
+fn fold_case_str(text: &str) -> String {
+    text.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+/// A field selector for `--nth`: a single 1-based index (negative counts
+/// from the end) or an inclusive range with optionally open ends
+pub struct FieldRange {
+    pub start: Option<isize>,
+    pub end: Option<isize>,
+}
+
+impl FieldRange {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parse_bound = |bound: &str| {
+            if bound.is_empty() {
+                Ok(None)
+            } else {
+                bound
+                    .parse::<isize>()
+                    .map(Some)
+                    .map_err(|_| format!("Invalid field index: {spec}"))
+            }
+        };
+
+        match spec.split_once("..") {
+            Some((start, end)) => Ok(Self {
+                start: parse_bound(start)?,
+                end: parse_bound(end)?,
+            }),
+
+            None => {
+                let index = parse_bound(spec)?;
+
+                Ok(Self {
+                    start: index,
+                    end: index,
+                })
+            }
+        }
+    }
+
+    /// Whether the 1-based field `index` (out of `count` fields) is selected
+    pub fn contains(&self, index: usize, count: usize) -> bool {
+        // Negative indices count from the end (`-1` is the last field)
+        let resolve = |bound: isize| {
+            if bound < 0 {
+                count as isize + 1 + bound
+            } else {
+                bound
+            }
+        };
+
+        let start = self.start.map_or(1, resolve);
+        let end = self.end.map_or(count as isize, resolve);
+
+        (start..=end).contains(&(index as isize))
+    }
+}
+
+/// Split a line into its fields with their starting character positions,
+/// using the literal delimiter, or runs of whitespace when there is none
+pub fn split_fields(line: &str, delimiter: Option<&str>) -> Vec<(usize, String)> {
+    match delimiter {
+        Some(delimiter) => {
+            let delimiter_chars = delimiter.chars().count();
+            let mut fields = vec![];
+            let mut position = 0;
+
+            for part in line.split(delimiter) {
+                fields.push((position, part.to_owned()));
+                position += part.chars().count() + delimiter_chars;
+            }
+
+            fields
+        }
+
+        None => {
+            let mut fields = vec![];
+            let mut current = String::new();
+            let mut start = 0;
+
+            for (i, c) in line.chars().enumerate() {
+                if c.is_whitespace() {
+                    if !current.is_empty() {
+                        fields.push((start, std::mem::take(&mut current)));
+                    }
+                } else {
+                    if current.is_empty() {
+                        start = i;
+                    }
+
+                    current.push(c);
+                }
+            }
+
+            if !current.is_empty() {
+                fields.push((start, current));
+            }
+
+            fields
+        }
+    }
+}
+
+/// With `--nth`, build the text a candidate is matched against (its selected
+/// fields joined by spaces), along with the mapping from that text's
+/// character positions back to the original line (for highlighting)
+fn match_text_for(line: &str, options: &MatchOptions) -> Option<(String, Vec<usize>)> {
+    if options.nth.is_empty() {
+        return None;
+    }
+
+    let fields = split_fields(line, options.delimiter.as_deref());
+    let count = fields.len();
+
+    let mut text = String::new();
+    let mut position_map = vec![];
+
+    for (index, (start, field)) in fields.into_iter().enumerate() {
+        if !options
+            .nth
+            .iter()
+            .any(|range| range.contains(index + 1, count))
+        {
+            continue;
+        }
+
+        if !text.is_empty() {
+            text.push(' ');
+
+            // The separator can never be matched (query terms are split on
+            // whitespace), so its mapping is arbitrary
+            position_map.push(start);
+        }
+
+        for (offset, c) in field.chars().enumerate() {
+            text.push(c);
+            position_map.push(start + offset);
+        }
+    }
+
+    Some((text, position_map))
+}
+
+/// With `--with-nth`, the text a candidate is displayed and matched as: its
+/// selected fields rejoined with the delimiter (`None` when the option is
+/// unset, i.e. the line is used as-is). When combined with `--nth`, field
+/// indices refer to the rebuilt text.
+fn display_text_for(line: &str, options: &MatchOptions) -> Option<String> {
+    if options.with_nth.is_empty() {
+        return None;
+    }
+
+    let fields = split_fields(line, options.delimiter.as_deref());
+    let count = fields.len();
+
+    let selected = fields
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            options
+                .with_nth
+                .iter()
+                .any(|range| range.contains(index + 1, count))
+        })
+        .map(|(_, (_, field))| field.as_str())
+        .collect::<Vec<_>>();
+
+    Some(selected.join(options.delimiter.as_deref().unwrap_or(" ")))
+}
+
+/// A candidate retained by [`fuzzy_find`]
+pub struct FuzzyMatch {
+    /// Position of the candidate in the original input list
+    pub original_index: usize,
+
+    /// Rebuilt display text when a transform (e.g. `--with-nth`) replaced
+    /// the line; `None` means the original line is used as-is, avoiding a
+    /// clone per match
+    pub transformed: Option<String>,
+
+    /// Character positions of the subject picked by the scorer's best
+    /// alignment, sorted ascending
+    pub matched_positions: Vec<usize>,
+}
+
+impl FuzzyMatch {
+    /// Text this candidate is displayed and matched as, borrowing from the
+    /// input list unless a transform rebuilt it
+    pub fn text<'a>(&'a self, list: &'a [String]) -> &'a str {
+        self.transformed
+            .as_deref()
+            .unwrap_or(&list[self.original_index])
+    }
+}
+
+pub fn fuzzy_find(query: &str, list: &[String], options: &MatchOptions) -> Vec<FuzzyMatch> {
+    fuzzy_find_with(query, list, options, options.algorithm.scorer())
+}
+
+/// Like [`fuzzy_find`], but with an explicit [`Scorer`] instead of the one
+/// selected by the options
+pub fn fuzzy_find_with(
+    query: &str,
+    list: &[String],
+    options: &MatchOptions,
+    scorer: &dyn Scorer,
+) -> Vec<FuzzyMatch> {
+    if options.regex {
+        return regex_find(query, list, options);
+    }
+
+    let terms = parse_query_terms(query, options);
+
+    if terms.is_empty() {
+        return list
+            .iter()
+            .enumerate()
+            .map(|(i, text)| FuzzyMatch {
+                original_index: i,
+                transformed: display_text_for(text, options),
+                matched_positions: vec![],
+            })
+            .collect();
+    }
+
+    let score_candidate = |(i, result): (usize, &String)| {
+        // Bonuses (e.g. frecency) are keyed on the full original line
+        let bonus = options.score_bonuses.get(result).copied().unwrap_or(0);
+
+        // `--with-nth` replaces the line with its rebuilt form for both
+        // display and matching
+        let transformed = display_text_for(result, options);
+        let result = transformed.as_deref().unwrap_or(result);
+
+        // Folding is 1:1 per character, so positions computed on the folded
+        // text directly index the original (displayed) text
+        let folded;
+        let result = if options.normalize {
+            folded = strip_diacritics_str(result);
+            &folded
+        } else {
+            result
+        };
+
+        // With `--nth`, match against the selected fields only and map the
+        // matched positions back onto the full line
+        let scored = match match_text_for(result, options) {
+            Some((text, position_map)) => {
+                compute_candidate_score(&terms, &text, scorer, options.case).map(
+                    |(score, positions)| {
+                        let positions = positions
+                            .into_iter()
+                            .map(|position| position_map[position])
+                            .collect::<Vec<_>>();
+
+                        (score, positions)
+                    },
+                )
+            }
+
+            None => compute_candidate_score(&terms, result, scorer, options.case),
+        };
+
+        // The transformed text is carried along so the sort can tie-break on
+        // what is actually displayed
+        scored.map(|(score, positions)| (i, score + bonus, positions, transformed))
+    };
+
+    // Scoring each candidate is independent, so big lists are scored in
+    // parallel; small ones stay on a single thread where the thread-pool
+    // overhead would dominate. Both paths preserve the input order.
+    let mut scores = if list.len() >= PARALLEL_SCORING_THRESHOLD {
+        list.par_iter()
+            .enumerate()
+            .filter_map(score_candidate)
+            .collect::<Vec<_>>()
+    } else {
+        list.iter()
+            .enumerate()
+            .filter_map(score_candidate)
+            .collect::<Vec<_>>()
+    };
+
+    // With `--no-sort` matches keep the original input ordering; when
+    // sorting, *higher* scores come first, and ties are broken
+    // deterministically by preferring shorter candidates, then the original
+    // input order
+    if !options.no_sort {
+        let tiebreak: &[Tiebreak] = if options.tiebreak.is_empty() {
+            &DEFAULT_TIEBREAK
+        } else {
+            &options.tiebreak
+        };
+
+        scores.sort_by(|a, b| {
+            // Length compares what is actually displayed, which `--with-nth`
+            // may have rebuilt from the original line
+            let length = |entry: &(usize, usize, Vec<usize>, Option<String>)| {
+                entry
+                    .3
+                    .as_deref()
+                    .unwrap_or(&list[entry.0])
+                    .chars()
+                    .count()
+            };
+
+            for criterion in tiebreak {
+                let ordering = match criterion {
+                    Tiebreak::Score => b.1.cmp(&a.1),
+                    Tiebreak::Length => length(a).cmp(&length(b)),
+                    Tiebreak::Begin => a.2.first().cmp(&b.2.first()),
+                    Tiebreak::Index => a.0.cmp(&b.0),
+                };
+
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+            }
+
+            // Stay deterministic whatever the criteria list was
+            a.0.cmp(&b.0)
+        });
+    }
+
+    scores
+        .into_iter()
+        .map(|(i, _, matched_positions, transformed)| FuzzyMatch {
+            original_index: i,
+            transformed,
+            matched_positions,
+        })
+        .collect()
+}
+
+/// Minimum number of candidates before scoring is spread over threads
+const PARALLEL_SCORING_THRESHOLD: usize = 4096;
+
+/// Base score awarded for every matched character
+const SCORE_MATCH: usize = 16;
+
+/// Additional score awarded for each character already part of the current
+/// run of consecutive matches (so longer runs escalate quadratically)
+const SCORE_CONSECUTIVE: usize = 8;
+
+/// Additional score awarded when a match lands on a word boundary: the very
+/// first character of the subject, right after a separator, or at a camelCase
+/// boundary
+const SCORE_BOUNDARY: usize = 24;
+
+/// Scale applied before dividing a raw score by its match span, keeping
+/// integer resolution in the normalized score
+const SCORE_SPAN_SCALE: usize = 64;
+
+/// Base that exact, anchored and regex match scores count down from, so
+/// earlier match positions rank higher *independently of the subject
+/// length* (equal positions tie, letting the length tiebreak apply)
+const SCORE_POSITION_BASE: usize = 1 << 20;
+
+/// Characters treated as word separators for [`SCORE_BOUNDARY`]
+const SEPARATORS: [char; 5] = ['/', '_', '-', '.', ' '];
+
+/// A single space-separated term of the query mini-language
+struct Term {
+    /// The candidate must *not* match this term
+    negated: bool,
+
+    /// Match as a contiguous substring instead of a fuzzy subsequence
+    exact: bool,
+
+    /// The candidate must *start* with this term (`^` prefix)
+    anchor_start: bool,
+
+    /// The candidate must *end* with this term (`$` suffix)
+    anchor_end: bool,
+
+    text: String,
+}
+
+/// Split the query into whitespace-separated terms, each matched
+/// independently against the candidate (so "src main rs" is three matches,
+/// not one subsequence containing spaces): a leading `!` negates a term, a
+/// leading single-quote makes it exact, and every (non-negated) term must
+/// match for a candidate to be kept
+fn parse_query_terms(query: &str, options: &MatchOptions) -> Vec<Term> {
+    query
+        .split_whitespace()
+        .filter_map(|term| {
+            let (negated, term) = match term.strip_prefix('!') {
+                Some(stripped) => (true, stripped),
+                None => (false, term),
+            };
+
+            let (exact, term) = match term.strip_prefix('\'') {
+                Some(stripped) => (true, stripped),
+                None => (options.exact, term),
+            };
+
+            // `^term` anchors to the start, `term$` to the end (both mean
+            // whole-candidate equality)
+            let (anchor_start, term) = match term.strip_prefix('^') {
+                Some(stripped) => (true, stripped),
+                None => (false, term),
+            };
+
+            let (anchor_end, term) = match term.strip_suffix('$') {
+                Some(stripped) => (true, stripped),
+                None => (false, term),
+            };
+
+            if term.is_empty() {
+                return None;
+            }
+
+            let text = if options.normalize {
+                strip_diacritics_str(term)
+            } else {
+                term.to_string()
+            };
+
+            Some(Term {
+                negated,
+                // Exclusions are about the candidate *containing* the term,
+                // so they always use substring semantics
+                exact: exact || negated,
+                anchor_start,
+                anchor_end,
+                text,
+            })
+        })
+        .collect()
+}
+
+/// Regex mode: keep the lines the query matches as a regular expression,
+/// ranked by match position (earlier is better) then match length, with the
+/// whole match span highlighted. A pattern that doesn't compile (common
+/// while still typing it) matches nothing instead of failing.
+fn regex_find(query: &str, list: &[String], options: &MatchOptions) -> Vec<FuzzyMatch> {
+    if query.is_empty() {
+        return list
+            .iter()
+            .enumerate()
+            .map(|(i, text)| FuzzyMatch {
+                original_index: i,
+                transformed: display_text_for(text, options),
+                matched_positions: vec![],
+            })
+            .collect();
+    }
+
+    let Ok(regex) = Regex::new(query) else {
+        return vec![];
+    };
+
+    let mut scores = list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let transformed = display_text_for(line, options);
+            let text = transformed.as_deref().unwrap_or(line);
+
+            let found = regex.find(text)?;
+
+            let start = text[..found.start()].chars().count();
+            let length = found.as_str().chars().count();
+
+            // Rank by match position (earlier is better) then match length,
+            // independent of how long the candidate is
+            let score = SCORE_POSITION_BASE.saturating_sub(start) * SCORE_SPAN_SCALE + length;
+            let matched_positions = (start..start + length).collect::<Vec<_>>();
+
+            Some((i, score, matched_positions, transformed))
+        })
+        .collect::<Vec<_>>();
+
+    if !options.no_sort {
+        scores.sort_by_cached_key(|(i, score, _, transformed)| {
+            let length = transformed
+                .as_deref()
+                .unwrap_or(&list[*i])
+                .chars()
+                .count();
+
+            (Reverse(*score), length, *i)
+        });
+    }
+
+    scores
+        .into_iter()
+        .map(|(i, _, matched_positions, transformed)| FuzzyMatch {
+            original_index: i,
+            transformed,
+            matched_positions,
+        })
+        .collect()
+}
+
+/// A pluggable scoring algorithm for (non-exact) query terms
+pub trait Scorer: Sync {
+    /// Score a query against a subject, returning `None` when it doesn't
+    /// match at all, and otherwise the score together with the matched
+    /// character positions of the subject (sorted ascending)
+    fn score(&self, query: &str, subject: &str, case: CaseMode) -> Option<(usize, Vec<usize>)>;
+
+    /// Whether matching is case-sensitive for this query, so highlighting
+    /// can stay consistent with the scorer's decisions
+    fn case_sensitive(&self, query: &str, case: CaseMode) -> bool {
+        is_case_sensitive(query, case)
+    }
+}
+
+/// The real scorer: the query must be an ordered subsequence of the subject,
+/// with the best-scoring alignment selected by [`compute_fuzzy_find_score`]
+pub struct SubsequenceScorer;
+
+impl Scorer for SubsequenceScorer {
+    fn score(&self, query: &str, subject: &str, case: CaseMode) -> Option<(usize, Vec<usize>)> {
+        compute_fuzzy_find_score(query, subject, case)
+    }
+}
+
+/// The historical character-frequency scorer, kept for comparison: counts
+/// how many times each query character appears anywhere in the subject,
+/// with no ordering requirement and no match positions
+pub struct NaiveScorer;
+
+impl Scorer for NaiveScorer {
+    fn score(&self, query: &str, subject: &str, case: CaseMode) -> Option<(usize, Vec<usize>)> {
+        let case_sensitive = is_case_sensitive(query, case);
+
+        let score = query
+            .chars()
+            .map(|c| {
+                subject
+                    .chars()
+                    .filter(|cc| chars_match(c, *cc, case_sensitive))
+                    .count()
+            })
+            .sum::<usize>();
+
+        (score > 0).then_some((score, vec![]))
+    }
+}
+
+/// Scoring algorithm selection for `--algo`
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Subsequence,
+    Naive,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "subsequence" => Ok(Self::Subsequence),
+            "naive" => Ok(Self::Naive),
+
+            _ => Err(format!("Unknown scoring algorithm: {name}")),
+        }
+    }
+
+    /// The [`Scorer`] implementing this algorithm
+    pub fn scorer(&self) -> &'static dyn Scorer {
+        match self {
+            Self::Subsequence => &SubsequenceScorer,
+            Self::Naive => &NaiveScorer,
+        }
+    }
+}
+
+/// Score a candidate against every term: any matching negated term
+/// disqualifies it, every positive term must match, the per-term scores are
+/// summed for ranking and the per-term matched positions are merged
+fn compute_candidate_score(
+    terms: &[Term],
+    subject: &str,
+    scorer: &dyn Scorer,
+    case: CaseMode,
+) -> Option<(usize, Vec<usize>)> {
+    let mut total = 0;
+    let mut weakest: Option<usize> = None;
+    let mut matched_positions = vec![];
+
+    for term in terms {
+        let result = if term.anchor_start || term.anchor_end {
+            compute_anchored_find_score(term, subject, case)
+        } else if term.exact {
+            compute_exact_find_score(&term.text, subject, case)
+        } else {
+            scorer.score(&term.text, subject, case)
+        };
+
+        if term.negated {
+            if result.is_some() {
+                return None;
+            }
+        } else {
+            let (score, positions) = result?;
+
+            total += score;
+            weakest = Some(weakest.map_or(score, |weakest| weakest.min(score)));
+            matched_positions.extend(positions);
+        }
+    }
+
+    // Weight the weakest term in so that a candidate matching *all* terms
+    // tightly outranks one where a single long accidental match dominates
+    // the sum
+    if let Some(weakest) = weakest {
+        total += weakest * 3;
+    }
+
+    matched_positions.sort_unstable();
+    matched_positions.dedup();
+
+    Some((total, matched_positions))
+}
+
+/// Score a candidate against an anchored term: the candidate must start with
+/// (`^`), end with (`$`), or — with both anchors — equal the term exactly
+/// (not fuzzily)
+fn compute_anchored_find_score(
+    term: &Term,
+    subject: &str,
+    case: CaseMode,
+) -> Option<(usize, Vec<usize>)> {
+    let (haystack, needle) = if is_case_sensitive(&term.text, case) {
+        (subject.to_owned(), term.text.clone())
+    } else {
+        (fold_case_str(subject), fold_case_str(&term.text))
+    };
+
+    let byte_pos = match (term.anchor_start, term.anchor_end) {
+        (true, true) => (haystack == needle).then_some(0)?,
+        (true, false) => haystack.starts_with(&needle).then_some(0)?,
+        (false, _) => haystack
+            .ends_with(&needle)
+            .then(|| haystack.len() - needle.len())?,
+    };
+
+    let start = haystack[..byte_pos].chars().count();
+    let matched_positions = (start..start + needle.chars().count()).collect();
+
+    Some((SCORE_POSITION_BASE.saturating_sub(byte_pos), matched_positions))
+}
+
+/// Score a candidate in exact mode: the query must appear as a contiguous
+/// substring, and earlier occurrences rank higher
+fn compute_exact_find_score(
+    query: &str,
+    subject: &str,
+    case: CaseMode,
+) -> Option<(usize, Vec<usize>)> {
+    // Folding is 1:1 per character, so the positions computed below index
+    // the *original* subject even when case folding would change character
+    // counts (e.g. 'İ')
+    let (haystack, needle) = if is_case_sensitive(query, case) {
+        (subject.to_owned(), query.to_owned())
+    } else {
+        (fold_case_str(subject), fold_case_str(query))
+    };
+
+    let byte_pos = haystack.find(&needle)?;
+
+    let start = haystack[..byte_pos].chars().count();
+    let matched_positions = (start..start + needle.chars().count()).collect();
+
+    Some((SCORE_POSITION_BASE.saturating_sub(byte_pos), matched_positions))
+}
+
+/// Whether matching should be case-sensitive for this query: under smart
+/// case it is if and only if the query contains an uppercase character
+fn is_case_sensitive(query: &str, case: CaseMode) -> bool {
+    match case {
+        CaseMode::Smart => query.chars().any(|c| c.is_uppercase()),
+        CaseMode::Insensitive => false,
+        CaseMode::Sensitive => true,
+    }
+}
+
+/// Compare two characters under the case mode chosen by [`is_case_sensitive`]
+fn chars_match(query_char: char, subject_char: char, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        query_char == subject_char
+    } else {
+        query_char.to_lowercase().eq(subject_char.to_lowercase())
+    }
+}
+
+/// Compute, once per candidate, the boundary bonus earned by a match landing
+/// on each position of the subject
+fn compute_boundary_bonuses(subject_chars: &[char]) -> Vec<usize> {
+    subject_chars
+        .iter()
+        .enumerate()
+        .map(|(j, c)| {
+            let on_boundary = match j.checked_sub(1).map(|prev| subject_chars[prev]) {
+                None => true,
+                Some(prev) => {
+                    SEPARATORS.contains(&prev) || (prev.is_lowercase() && c.is_uppercase())
+                }
+            };
+
+            if on_boundary {
+                SCORE_BOUNDARY
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+pub fn compute_fuzzy_find_score(
+    query: &str,
+    subject: &str,
+    case: CaseMode,
+) -> Option<(usize, Vec<usize>)> {
+    let query_chars = query.chars().collect::<Vec<_>>();
+    let subject_chars = subject.chars().collect::<Vec<_>>();
+
+    if query_chars.is_empty() || query_chars.len() > subject_chars.len() {
+        return None;
+    }
+
+    let case_sensitive = is_case_sensitive(query, case);
+
+    // Dynamic programming over all the ways the query can be embedded as an
+    // ordered subsequence of the subject, so a short query with many candidate
+    // positions settles on the alignment maximizing consecutive runs instead
+    // of the first greedy one.
+    //
+    // `rows[i][j]` is the best cell achievable when query character `i` is
+    // matched at subject position `j`; every row is kept so the winning
+    // alignment can be backtracked through the `prev` pointers.
+    let mut rows: Vec<Vec<Option<MatchCell>>> = Vec::with_capacity(query_chars.len());
+
+    let boundary_bonuses = compute_boundary_bonuses(&subject_chars);
+
+    for (i, query_char) in query_chars.iter().enumerate() {
+        let mut row: Vec<Option<MatchCell>> = vec![None; subject_chars.len()];
+
+        // Best `(score, position)` among `rows[i - 1][..j - 1]`, i.e.
+        // predecessors that are *not* adjacent to the current position (an
+        // adjacent predecessor always means the run continues instead)
+        let mut best_before: Option<(usize, usize)> = None;
+
+        for (j, subject_char) in subject_chars.iter().enumerate() {
+            if i > 0 && j >= 2 {
+                if let Some(cell) = &rows[i - 1][j - 2] {
+                    if best_before.is_none_or(|(score, _)| cell.score > score) {
+                        best_before = Some((cell.score, j - 2));
+                    }
+                }
+            }
+
+            if !chars_match(*query_char, *subject_char, case_sensitive) {
+                continue;
+            }
+
+            let char_score = SCORE_MATCH + boundary_bonuses[j];
+
+            // Extend a run of consecutive matched characters
+            let mut best = if i > 0 && j > 0 {
+                rows[i - 1][j - 1].as_ref().map(|cell| MatchCell {
+                    score: cell.score + char_score + cell.run * SCORE_CONSECUTIVE,
+                    run: cell.run + 1,
+                    prev: Some(j - 1),
+                })
+            } else {
+                None
+            };
+
+            // Or start a fresh run after a gap
+            let fresh = if i == 0 {
+                Some(MatchCell {
+                    score: char_score,
+                    run: 1,
+                    prev: None,
+                })
+            } else {
+                best_before.map(|(score, prev)| MatchCell {
+                    score: score + char_score,
+                    run: 1,
+                    prev: Some(prev),
+                })
+            };
+
+            if let Some(fresh) = fresh {
+                if best
+                    .as_ref()
+                    .is_none_or(|best_cell| fresh.score > best_cell.score)
+                {
+                    best = Some(fresh);
+                }
+            }
+
+            row[j] = best;
+        }
+
+        rows.push(row);
+    }
+
+    // Backtrack the best alignment from the best-scoring final cell
+    let (mut j, mut cell) = rows
+        .last()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cell)| cell.as_ref().map(|cell| (j, cell)))
+        .max_by_key(|(_, cell)| cell.score)?;
+
+    let score = cell.score;
+
+    let mut matched_positions = vec![j];
+
+    for i in (0..query_chars.len() - 1).rev() {
+        j = cell.prev.unwrap();
+        cell = rows[i][j].as_ref().unwrap();
+        matched_positions.push(j);
+    }
+
+    matched_positions.reverse();
+
+    // Normalize by the span the match covers, so a tight match in a short
+    // string outranks a loose one accumulating bonuses across a long string
+    let span = matched_positions.last().unwrap() - matched_positions.first().unwrap() + 1;
+
+    Some((score * SCORE_SPAN_SCALE / span, matched_positions))
+}
+
+/// One cell of the [`compute_fuzzy_find_score`] scoring matrix
+#[derive(Clone)]
+struct MatchCell {
+    score: usize,
+
+    /// Length of the run of consecutive matches ending here
+    run: usize,
+
+    /// Subject position the previous query character was matched at, for
+    /// backtracking (`None` on the first query character)
+    prev: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only used by the benchmark harness
+    use criterion as _;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn returned_items_are_members_of_the_input(
+            list in prop::collection::vec("[ -~]{0,12}", 0..8),
+            query in "[a-z]{0,5}",
+        ) {
+            for result in fuzzy_find(&query, &list, &MatchOptions::default()) {
+                prop_assert_eq!(&list[result.original_index], result.text(&list));
+            }
+        }
+
+        #[test]
+        fn empty_query_returns_the_input_unchanged(
+            list in prop::collection::vec("[ -~]{0,12}", 0..8),
+        ) {
+            let results = fuzzy_find("", &list, &MatchOptions::default())
+                .into_iter()
+                .map(|result| result.text(&list).to_owned())
+                .collect::<Vec<_>>();
+
+            prop_assert_eq!(results, list);
+        }
+
+        #[test]
+        fn a_subsequence_of_an_item_always_returns_at_least_that_item(
+            item in "[a-z]{1,12}",
+            keep in prop::collection::vec(any::<bool>(), 12),
+        ) {
+            let query = item
+                .chars()
+                .zip(keep)
+                .filter(|(_, keep)| *keep)
+                .map(|(c, _)| c)
+                .collect::<String>();
+
+            let list = vec![item.clone()];
+
+            // An empty query lists everything, which still includes the item
+            let results = fuzzy_find(&query, &list, &MatchOptions::default());
+
+            prop_assert!(results.iter().any(|result| result.text(&list) == item));
+        }
+
+        #[test]
+        fn results_are_sorted_by_descending_score(
+            list in prop::collection::vec("[a-z]{0,12}", 0..8),
+            query in "[a-z]{1,4}",
+        ) {
+            // A single lowercase term, so every candidate's score is exactly
+            // the scorer's output for it
+            let results = fuzzy_find(&query, &list, &MatchOptions::default());
+
+            let scores = results
+                .iter()
+                .map(|result| compute_fuzzy_find_score(&query, result.text(&list), CaseMode::default()).unwrap().0)
+                .collect::<Vec<_>>();
+
+            prop_assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+        }
+    }
+
+    #[test]
+    fn highlight_positions_reference_the_original_string() {
+        let options = MatchOptions::default();
+
+        // 'İ' expands to two characters under `str::to_lowercase`, which
+        // used to shift every matched position after it by one
+        let list = vec!["İstanbul-FILE.rs".to_owned()];
+
+        let results = fuzzy_find("'file", &list, &options);
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].matched_positions, vec![9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn tiebreak_begin_prefers_matches_starting_earlier() {
+        let options = MatchOptions {
+            tiebreak: vec![Tiebreak::Begin, Tiebreak::Index],
+            ..MatchOptions::default()
+        };
+
+        // "b" matches at position 0 in the second candidate only
+        let list = vec!["ab".to_owned(), "ba".to_owned()];
+
+        let results = fuzzy_find("b", &list, &options)
+            .into_iter()
+            .map(|result| result.text(&list).to_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, vec!["ba".to_owned(), "ab".to_owned()]);
+    }
+
+    #[test]
+    fn tiebreak_index_keeps_the_input_order() {
+        let options = MatchOptions {
+            tiebreak: vec![Tiebreak::Index],
+            ..MatchOptions::default()
+        };
+
+        let list = vec!["xa".to_owned(), "a".to_owned(), "ax".to_owned()];
+
+        let results = fuzzy_find("a", &list, &options)
+            .into_iter()
+            .map(|result| result.original_index)
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn multi_term_queries_prefer_balanced_matches() {
+        let options = MatchOptions::default();
+
+        // Both terms match tightly in the first candidate; the second has a
+        // perfect first term but a barely-matching second one
+        let list = vec![
+            "abxxxxxxxxcxxxxxxxxd".to_owned(),
+            "ab cd".to_owned(),
+        ];
+
+        let results = fuzzy_find("ab cd", &list, &options)
+            .into_iter()
+            .map(|result| result.text(&list).to_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec!["ab cd".to_owned(), "abxxxxxxxxcxxxxxxxxd".to_owned()]
+        );
+    }
+
+    #[test]
+    fn tight_matches_outrank_loose_ones() {
+        let options = MatchOptions::default();
+
+        // The loose match racks up boundary bonuses, but covers a much
+        // wider span
+        let list = vec!["m-a-i-n-frame.txt".to_owned(), "main.rs".to_owned()];
+
+        let results = fuzzy_find("main", &list, &options)
+            .into_iter()
+            .map(|result| result.text(&list).to_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec!["main.rs".to_owned(), "m-a-i-n-frame.txt".to_owned()]
+        );
+    }
+
+    #[test]
+    fn higher_scores_rank_first() {
+        let options = MatchOptions::default();
+
+        let list = vec!["axbxxxx".to_owned(), "ab".to_owned()];
+
+        let results = fuzzy_find("ab", &list, &options)
+            .into_iter()
+            .map(|result| result.text(&list).to_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, vec!["ab".to_owned(), "axbxxxx".to_owned()]);
+    }
+
+    #[test]
+    fn equal_scores_prefer_shorter_candidates_then_original_order() {
+        let options = MatchOptions::default();
+
+        // "ab_" scores exactly like "ab" for this query, but is longer
+        let list = vec!["ab_".to_owned(), "ab".to_owned(), "ab_".to_owned()];
+
+        let results = fuzzy_find("ab", &list, &options)
+            .into_iter()
+            .map(|result| (result.original_index, result.text(&list).to_owned()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec![
+                (1, "ab".to_owned()),
+                (0, "ab_".to_owned()),
+                (2, "ab_".to_owned()),
+            ]
+        );
+    }
+}